@@ -1,5 +1,6 @@
 use std::io;
 
+use at2_node::client::{Client, QuorumClient};
 use drop::crypto::sign;
 use http::Uri;
 use snafu::{ResultExt, Snafu};
@@ -10,6 +11,36 @@ pub struct Config {
     pub rpc_address: Uri,
     #[serde(with = "hex")]
     pub private_key: sign::PrivateKey,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub node: Option<ConfigNode>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub quorum: Option<ConfigQuorum>,
+}
+
+/// Node expected to answer, its replies checked against its key
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigNode {
+    #[serde(with = "hex")]
+    pub public_key: sign::PublicKey,
+}
+
+/// Other nodes to ask, a reply being trusted only once enough of them agree on it
+///
+/// The configured node has to be pinned too, see [`ConfigNode`].
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigQuorum {
+    /// How many nodes have to agree, including the configured one
+    pub size: usize,
+    /// Each other node
+    pub nodes: Vec<ConfigQuorumNode>,
+}
+
+/// Other node to ask, its replies checked against its key
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigQuorumNode {
+    pub rpc_address: String,
+    #[serde(with = "hex")]
+    pub public_key: sign::PublicKey,
 }
 
 #[derive(Debug, Snafu)]
@@ -23,6 +54,13 @@ pub enum Error {
     Read { source: io::Error },
     #[snafu(display("decode: {}", source))]
     Decode { source: toml::de::Error },
+    #[snafu(display("invalid quorum address {}: {}", address, source))]
+    InvalidQuorumAddress {
+        address: String,
+        source: http::uri::InvalidUri,
+    },
+    #[snafu(display("invalid quorum: {}", source))]
+    InvalidQuorum { source: at2_node::client::Error },
 }
 
 pub fn from_reader(mut reader: impl io::Read) -> Result<Config, Error> {
@@ -35,6 +73,45 @@ pub fn from_reader(mut reader: impl io::Read) -> Result<Config, Error> {
 }
 
 impl Config {
+    /// Client for the configured node
+    pub fn client(&self) -> Client {
+        let client = Client::new(self.rpc_address.clone());
+
+        match &self.node {
+            Some(node) => client.with_node_key(node.public_key),
+            None => client,
+        }
+    }
+
+    /// Client for the configured node and the quorum ones, if any
+    pub fn quorum_client(&self) -> Result<Option<QuorumClient>, Error> {
+        let quorum = match &self.quorum {
+            Some(quorum) => quorum,
+            None => return Ok(None),
+        };
+
+        let mut addresses = vec![self.rpc_address.clone()];
+        let mut clients = vec![self.client()];
+        for node in &quorum.nodes {
+            let address = node
+                .rpc_address
+                .parse::<Uri>()
+                .context(InvalidQuorumAddress {
+                    address: &node.rpc_address,
+                })?;
+            if addresses.contains(&address) {
+                continue;
+            }
+
+            clients.push(Client::new(address.clone()).with_node_key(node.public_key));
+            addresses.push(address);
+        }
+
+        QuorumClient::new(clients, quorum.size)
+            .context(InvalidQuorum)
+            .map(Some)
+    }
+
     pub fn to_writer(&self, mut writer: impl io::Write) -> Result<(), Error> {
         let encoded = toml::to_vec(&self).context(Encode)?;
 
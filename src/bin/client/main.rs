@@ -2,9 +2,12 @@ use std::io::{stdin, stdout};
 
 use at2_node::{
     client::{self, Client},
-    TransactionState,
+    wallet::{self, Wallet},
+    FailureReason, FullTransaction, HistoryDirection, MembershipChange, ThinTransaction,
+    TransactionState, NATIVE_ASSET,
 };
-use drop::crypto::sign;
+use drop::crypto::{key::exchange, sign};
+use futures::StreamExt;
 use hex::FromHex;
 use http::Uri;
 use snafu::{ResultExt, Snafu};
@@ -16,23 +19,157 @@ fn hex_decode<T: FromHex>(src: &str) -> Result<T, T::Error> {
     T::from_hex(src)
 }
 
+fn parse_output(src: &str) -> Result<(sign::PublicKey, u64, Option<String>), String> {
+    let mut parts = src.splitn(3, ':');
+    let (recipient, amount) = parts
+        .next()
+        .zip(parts.next())
+        .ok_or_else(|| format!("not as recipient:amount[:memo]: {}", src))?;
+
+    Ok((
+        hex_decode(recipient).map_err(|err| format!("invalid recipient: {}", err))?,
+        amount
+            .parse()
+            .map_err(|err| format!("invalid amount: {}", err))?,
+        parts.next().map(str::to_owned),
+    ))
+}
+
+fn parse_direction(src: &str) -> Result<HistoryDirection, String> {
+    match src {
+        "sent" => Ok(HistoryDirection::Sent),
+        "received" => Ok(HistoryDirection::Received),
+        "both" => Ok(HistoryDirection::Both),
+        _ => Err(format!("unknown direction: {}", src)),
+    }
+}
+
 #[derive(Debug, StructOpt)]
 enum Commands {
     Config(CommandsConfig),
+    CreateAccount,
     SendAsset {
-        sequence: sieve::Sequence,
         #[structopt(parse(try_from_str = hex_decode))]
         recipient: sign::PublicKey,
         amount: u64,
+        /// Asset to send
+        #[structopt(long, default_value = NATIVE_ASSET)]
+        asset: String,
+        /// Sequence to use, the one following the last used if not given
+        #[structopt(long)]
+        sequence: Option<sieve::Sequence>,
+        /// Text to attach to the transaction, such as an invoice id
+        #[structopt(long)]
+        memo: Option<String>,
+        /// Wait for the transaction to be processed, failing if it was rejected,
+        /// always done when not giving the sequence
+        #[structopt(long)]
+        wait: bool,
+    },
+    /// Send to many recipients at once, every output being applied or none
+    SendBatch {
+        /// Recipient with the amount to send and an optional text to attach, as
+        /// recipient:amount[:memo]
+        #[structopt(required = true, parse(try_from_str = parse_output))]
+        outputs: Vec<(sign::PublicKey, u64, Option<String>)>,
+        /// Asset to send
+        #[structopt(long, default_value = NATIVE_ASSET)]
+        asset: String,
+        /// Sequence to use, the one following the last used if not given
+        #[structopt(long)]
+        sequence: Option<sieve::Sequence>,
+    },
+    IssueAsset {
+        asset: String,
+        amount: u64,
+        /// Sequence to use, the one following the last used if not given
+        #[structopt(long)]
+        sequence: Option<sieve::Sequence>,
+    },
+    GetBalance {
+        /// Asset to show the balance of
+        #[structopt(long, default_value = NATIVE_ASSET)]
+        asset: String,
+        /// Show the balance of every owned asset instead
+        #[structopt(long)]
+        all: bool,
+        /// Verify that the account is part of the state signed by the node
+        #[structopt(long)]
+        prove: bool,
     },
-    GetBalance,
     GetLastSequence,
     GetLatestTransactions,
+    GetTransaction {
+        sequence: sieve::Sequence,
+        /// Sender of the transaction, yourself if not given
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        sender: Option<sign::PublicKey>,
+    },
+    WatchTransaction {
+        sequence: sieve::Sequence,
+        /// Sender of the transaction, yourself if not given
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        sender: Option<sign::PublicKey>,
+    },
+    GetHistory {
+        /// Which transactions to show: sent, received or both
+        #[structopt(long, default_value = "both", parse(try_from_str = parse_direction))]
+        direction: HistoryDirection,
+        /// Where to start, as given by a previous call
+        #[structopt(long, default_value = "0")]
+        cursor: u64,
+        /// How many transactions to show, the node's maximum if zero
+        #[structopt(long, default_value = "0")]
+        page_size: u32,
+        /// User to show the history of, yourself if not given
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        user: Option<sign::PublicKey>,
+    },
+    SubscribeTransactions {
+        /// Only show transactions sent by this user
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        sender: Option<sign::PublicKey>,
+        /// Only show transactions received by this user
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        recipient: Option<sign::PublicKey>,
+    },
+    /// Show the root of the node's state, to compare with the other nodes
+    GetStateRoot,
+    GetPeers,
+    /// Show the number of transactions the node has yet to apply, by sender
+    GetPending,
+    /// Make the network connect to a new node, only allowed for the admin
+    AddNode {
+        #[structopt(parse(try_from_str = hex_decode))]
+        public_key: exchange::PublicKey,
+        address: String,
+        /// Where the node serves its RPC, for the others to catch up with it
+        rpc_address: String,
+        /// Key signing the node's replies
+        #[structopt(parse(try_from_str = hex_decode))]
+        sign_key: sign::PublicKey,
+        /// Sequence to use, the one following the last used if not given
+        #[structopt(long)]
+        sequence: Option<sieve::Sequence>,
+    },
+    /// Make the network disconnect from a node, only allowed for the admin
+    RemoveNode {
+        #[structopt(parse(try_from_str = hex_decode))]
+        public_key: exchange::PublicKey,
+        /// Sequence to use, the one following the last used if not given
+        #[structopt(long)]
+        sequence: Option<sieve::Sequence>,
+    },
 }
 
 #[derive(Debug, StructOpt)]
 enum CommandsConfig {
-    New { rpc_address: Uri },
+    New {
+        rpc_address: Uri,
+        /// Signing key of the node, to only accept its replies
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        node_public_key: Option<sign::PublicKey>,
+    },
     GetPublicKey,
 }
 
@@ -44,27 +181,60 @@ enum CommandError {
     Serialize { source: bincode::Error },
     #[snafu(display("client: {}", source))]
     ClientError { source: client::Error },
+    #[snafu(display("wallet: {}", source))]
+    WalletError { source: wallet::Error },
+    #[snafu(display("transaction failed: {}", reason))]
+    TransactionFailed { reason: FailureReason },
+    #[snafu(display("transaction unresolved"))]
+    Unresolved,
 }
 
 #[derive(Debug, Snafu)]
 enum CommandsError {
     #[snafu(display("config: {}", source))]
     Config { source: config::Error },
+    #[snafu(display("create account: {}", source))]
+    CreateAccount { source: CommandError },
     #[snafu(display("send asset: {}", source))]
     SendAsset { source: CommandError },
+    #[snafu(display("send batch: {}", source))]
+    SendBatch { source: CommandError },
+    #[snafu(display("issue asset: {}", source))]
+    IssueAsset { source: CommandError },
     #[snafu(display("get asset: {}", source))]
     GetBalance { source: CommandError },
     #[snafu(display("get last sequence: {}", source))]
     GetLastSequence { source: CommandError },
     #[snafu(display("get latest transactions: {}", source))]
     GetLatestTransactions { source: CommandError },
+    #[snafu(display("get transaction: {}", source))]
+    GetTransaction { source: CommandError },
+    #[snafu(display("watch transaction: {}", source))]
+    WatchTransaction { source: CommandError },
+    #[snafu(display("get history: {}", source))]
+    GetHistory { source: CommandError },
+    #[snafu(display("subscribe transactions: {}", source))]
+    SubscribeTransactions { source: CommandError },
+    #[snafu(display("get state root: {}", source))]
+    GetStateRoot { source: CommandError },
+    #[snafu(display("get peers: {}", source))]
+    GetPeers { source: CommandError },
+    #[snafu(display("get pending: {}", source))]
+    GetPending { source: CommandError },
+    #[snafu(display("change membership: {}", source))]
+    ChangeMembership { source: CommandError },
 }
 
 fn config(cmd: CommandsConfig) -> Result<(), config::Error> {
     match cmd {
-        CommandsConfig::New { rpc_address } => config::Config {
+        CommandsConfig::New {
+            rpc_address,
+            node_public_key,
+        } => config::Config {
             rpc_address,
             private_key: sign::KeyPair::random().private(),
+            node: node_public_key.map(|public_key| config::ConfigNode { public_key }),
+            quorum: None,
         }
         .to_writer(stdout()),
         CommandsConfig::GetPublicKey => {
@@ -77,35 +247,200 @@ fn config(cmd: CommandsConfig) -> Result<(), config::Error> {
     }
 }
 
+async fn create_account() -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    config
+        .client()
+        .create_account(&sign::KeyPair::from(config.private_key))
+        .await
+        .context(ClientError)?;
+
+    Ok(())
+}
+
 async fn send_asset(
-    sequence: sieve::Sequence,
     recipient: sign::PublicKey,
+    asset: String,
     amount: u64,
+    sequence: Option<sieve::Sequence>,
+    memo: Option<String>,
+    wait: bool,
 ) -> Result<(), CommandError> {
     let config = config::from_reader(stdin()).context(ReadConfig)?;
 
-    Client::new(config.rpc_address)
-        .send_asset(
-            &sign::KeyPair::from(config.private_key),
+    let mut client = config.client();
+    let user = sign::KeyPair::from(config.private_key);
+
+    let tx = match sequence {
+        None => Wallet::new(client, user)
+            .send_asset(recipient, asset, amount, memo)
+            .await
+            .context(WalletError)?,
+        Some(sequence) if !wait => {
+            return client
+                .send_asset(&user, sequence, recipient, asset, amount, memo)
+                .await
+                .context(ClientError)
+        }
+        Some(sequence) => client
+            .send_asset_and_wait(&user, sequence, recipient, asset, amount, memo)
+            .await
+            .context(ClientError)?,
+    };
+
+    print_transaction(&tx);
+
+    match tx.state {
+        TransactionState::Failure(reason) => TransactionFailed { reason }.fail(),
+        _ => Ok(()),
+    }
+}
+
+async fn send_batch(
+    outputs: Vec<(sign::PublicKey, u64, Option<String>)>,
+    asset: String,
+    sequence: Option<sieve::Sequence>,
+) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let mut client = config.client();
+    let user = sign::KeyPair::from(config.private_key);
+
+    let sequence = sequence_or_next(&mut client, &user, sequence).await?;
+
+    client
+        .send_batch(
+            &user,
             sequence,
-            recipient,
-            amount,
+            outputs
+                .into_iter()
+                .map(|(recipient, amount, memo)| ThinTransaction {
+                    recipient,
+                    asset: asset.clone(),
+                    amount,
+                    memo,
+                })
+                .collect(),
         )
         .await
         .context(ClientError)?;
 
-    Ok(())
+    let mut states = Box::pin(
+        client
+            .watch_transaction(&user.public(), sequence)
+            .await
+            .context(ClientError)?,
+    );
+    let mut state = None;
+    while let Some(tx) = states.next().await {
+        state = Some(tx.context(ClientError)?.state);
+    }
+
+    client
+        .get_transaction(&user.public(), sequence)
+        .await
+        .context(ClientError)?
+        .iter()
+        .for_each(print_transaction);
+
+    match state {
+        None | Some(TransactionState::Pending) => Unresolved.fail(),
+        Some(TransactionState::Failure(reason)) => TransactionFailed { reason }.fail(),
+        Some(TransactionState::Success) => Ok(()),
+    }
 }
 
-async fn get_balance() -> Result<(), CommandError> {
+async fn issue_asset(
+    asset: String,
+    amount: u64,
+    sequence: Option<sieve::Sequence>,
+) -> Result<(), CommandError> {
     let config = config::from_reader(stdin()).context(ReadConfig)?;
 
-    let amount = Client::new(config.rpc_address)
-        .get_balance(&sign::KeyPair::from(config.private_key).public())
+    let mut client = config.client();
+    let user = sign::KeyPair::from(config.private_key);
+
+    let sequence = sequence_or_next(&mut client, &user, sequence).await?;
+
+    client
+        .issue_asset(&user, sequence, asset, amount)
+        .await
+        .context(ClientError)
+}
+
+async fn change_membership(
+    change: MembershipChange,
+    sequence: Option<sieve::Sequence>,
+) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let mut client = config.client();
+    let user = sign::KeyPair::from(config.private_key);
+
+    let sequence = sequence_or_next(&mut client, &user, sequence).await?;
+
+    client
+        .change_membership(&user, sequence, change)
         .await
+        .context(ClientError)
+}
+
+/// Return the given sequence, or the one following the last used by the user
+async fn sequence_or_next(
+    client: &mut Client,
+    user: &sign::KeyPair,
+    sequence: Option<sieve::Sequence>,
+) -> Result<sieve::Sequence, CommandError> {
+    match sequence {
+        Some(sequence) => Ok(sequence),
+        None => Ok(client
+            .get_last_sequence(&user.public())
+            .await
+            .context(ClientError)?
+            + 1),
+    }
+}
+
+async fn get_balance(asset: String, all: bool, prove: bool) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let mut client = config.client();
+    let user = sign::KeyPair::from(config.private_key).public();
+
+    if prove {
+        let account = client
+            .get_proven_account(&user)
+            .await
+            .context(ClientError)?;
+
+        if all {
+            account
+                .balances
+                .iter()
+                .for_each(|(asset, amount)| println!("{} {}", asset, amount));
+        } else {
+            println!(
+                "{}",
+                account.balances.get(&asset).copied().unwrap_or_default()
+            );
+        }
+    } else if all {
+        client
+            .get_portfolio(&user)
+            .await
+            .context(ClientError)?
+            .iter()
+            .for_each(|(asset, amount)| println!("{} {}", asset, amount));
+    } else {
+        let amount = match config.quorum_client().context(ReadConfig)? {
+            Some(quorum) => quorum.get_asset_balance(&user, asset).await,
+            None => client.get_asset_balance(&user, asset).await,
+        }
         .context(ClientError)?;
 
-    println!("{}", amount);
+        println!("{}", amount);
+    }
 
     Ok(())
 }
@@ -113,10 +448,13 @@ async fn get_balance() -> Result<(), CommandError> {
 async fn get_last_sequence() -> Result<(), CommandError> {
     let config = config::from_reader(stdin()).context(ReadConfig)?;
 
-    let sequence = Client::new(config.rpc_address)
-        .get_last_sequence(&sign::KeyPair::from(config.private_key).public())
-        .await
-        .context(ClientError)?;
+    let user = sign::KeyPair::from(config.private_key).public();
+
+    let sequence = match config.quorum_client().context(ReadConfig)? {
+        Some(quorum) => quorum.get_last_sequence(&user).await,
+        None => config.client().get_last_sequence(&user).await,
+    }
+    .context(ClientError)?;
 
     println!("{}", sequence);
 
@@ -126,45 +464,276 @@ async fn get_last_sequence() -> Result<(), CommandError> {
 async fn get_latest_transactions() -> Result<(), CommandError> {
     let config = config::from_reader(stdin()).context(ReadConfig)?;
 
-    Client::new(config.rpc_address)
+    config
+        .client()
         .get_latest_transactions()
         .await
         .context(ClientError)?
         .iter()
-        .for_each(|tx| {
+        .for_each(print_transaction);
+
+    Ok(())
+}
+
+async fn get_transaction(
+    sequence: sieve::Sequence,
+    sender: Option<sign::PublicKey>,
+) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let sender = sender.unwrap_or_else(|| sign::KeyPair::from(config.private_key).public());
+
+    let mut outputs = config
+        .client()
+        .get_transaction(&sender, sequence)
+        .await
+        .context(ClientError)?;
+    // only the state is agreed on, the rest being local to each node
+    if let Some(quorum) = config.quorum_client().context(ReadConfig)? {
+        let state = quorum
+            .get_transaction_state(&sender, sequence)
+            .await
+            .context(ClientError)?;
+        outputs.iter_mut().for_each(|tx| tx.state = state.clone());
+    }
+
+    outputs.iter().for_each(print_transaction);
+
+    Ok(())
+}
+
+async fn watch_transaction(
+    sequence: sieve::Sequence,
+    sender: Option<sign::PublicKey>,
+) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let mut states = Box::pin(
+        config
+            .client()
+            .watch_transaction(
+                &sender.unwrap_or_else(|| sign::KeyPair::from(config.private_key).public()),
+                sequence,
+            )
+            .await
+            .context(ClientError)?,
+    );
+
+    while let Some(tx) = states.next().await {
+        print_transaction(&tx.context(ClientError)?);
+    }
+
+    Ok(())
+}
+
+async fn get_history(
+    direction: HistoryDirection,
+    cursor: u64,
+    page_size: u32,
+    user: Option<sign::PublicKey>,
+) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let page = config
+        .client()
+        .get_account_history(
+            &user.unwrap_or_else(|| sign::KeyPair::from(config.private_key).public()),
+            direction,
+            cursor,
+            page_size,
+        )
+        .await
+        .context(ClientError)?;
+
+    page.transactions.iter().for_each(print_transaction);
+    eprintln!("next cursor: {}", page.next_cursor);
+
+    Ok(())
+}
+
+async fn subscribe_transactions(
+    sender: Option<sign::PublicKey>,
+    recipient: Option<sign::PublicKey>,
+) -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let mut txs = Box::pin(
+        config
+            .client()
+            .subscribe_transactions(sender.as_ref(), recipient.as_ref())
+            .await
+            .context(ClientError)?,
+    );
+
+    while let Some(tx) = txs.next().await {
+        print_transaction(&tx.context(ClientError)?);
+    }
+
+    Ok(())
+}
+
+async fn get_state_root() -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    let state_root = config
+        .client()
+        .get_state_root()
+        .await
+        .context(ClientError)?;
+
+    println!("{}", hex::encode(state_root));
+
+    Ok(())
+}
+
+async fn get_peers() -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    config
+        .client()
+        .get_peers()
+        .await
+        .context(ClientError)?
+        .iter()
+        .for_each(|peer| {
             println!(
-                "{}: {} send {}¤ to {} ({})",
-                tx.timestamp,
-                tx.sender,
-                tx.amount,
-                tx.recipient,
-                match tx.state {
-                    TransactionState::Pending => "pending",
-                    TransactionState::Success => "success",
-                    TransactionState::Failure => "failure",
+                "{} {}: {}, last seen {}, {} reconnect attempts",
+                peer.public_key,
+                peer.address,
+                if peer.connected {
+                    "connected"
+                } else {
+                    "disconnected"
                 },
+                peer.last_seen
+                    .map(|last_seen| last_seen.to_string())
+                    .unwrap_or_else(|| "never".to_owned()),
+                peer.reconnect_attempts,
             )
         });
 
     Ok(())
 }
 
+async fn get_pending() -> Result<(), CommandError> {
+    let config = config::from_reader(stdin()).context(ReadConfig)?;
+
+    config
+        .client()
+        .get_pending()
+        .await
+        .context(ClientError)?
+        .iter()
+        .for_each(|(sender, depth)| println!("{}: {} pending", sender, depth));
+
+    Ok(())
+}
+
+fn print_transaction(tx: &FullTransaction) {
+    println!(
+        "{}: {} send {}{} to {}{} ({})",
+        tx.timestamp,
+        tx.sender,
+        tx.amount,
+        if tx.asset == NATIVE_ASSET {
+            "¤".to_owned()
+        } else {
+            format!(" {}", tx.asset)
+        },
+        tx.recipient,
+        tx.memo
+            .as_ref()
+            .map(|memo| format!(" for \"{}\"", memo))
+            .unwrap_or_default(),
+        match tx.state {
+            TransactionState::Pending => "pending".to_owned(),
+            TransactionState::Success => "success".to_owned(),
+            TransactionState::Failure(reason) => format!("failure: {}", reason),
+        },
+    )
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
     let ret = match Commands::from_args() {
         Commands::Config(cmd) => config(cmd).context(Config),
+        Commands::CreateAccount => create_account().await.context(CreateAccount),
         Commands::SendAsset {
-            sequence,
             recipient,
+            asset,
             amount,
-        } => send_asset(sequence, recipient, amount)
+            sequence,
+            memo,
+            wait,
+        } => send_asset(recipient, asset, amount, sequence, memo, wait)
             .await
             .context(SendAsset),
-        Commands::GetBalance => get_balance().await.context(GetBalance),
+        Commands::SendBatch {
+            outputs,
+            asset,
+            sequence,
+        } => send_batch(outputs, asset, sequence)
+            .await
+            .context(SendBatch),
+        Commands::IssueAsset {
+            asset,
+            amount,
+            sequence,
+        } => issue_asset(asset, amount, sequence)
+            .await
+            .context(IssueAsset),
+        Commands::GetBalance { asset, all, prove } => {
+            get_balance(asset, all, prove).await.context(GetBalance)
+        }
         Commands::GetLastSequence => get_last_sequence().await.context(GetLastSequence),
         Commands::GetLatestTransactions => get_latest_transactions()
             .await
             .context(GetLatestTransactions),
+        Commands::GetTransaction { sequence, sender } => get_transaction(sequence, sender)
+            .await
+            .context(GetTransaction),
+        Commands::WatchTransaction { sequence, sender } => watch_transaction(sequence, sender)
+            .await
+            .context(WatchTransaction),
+        Commands::GetHistory {
+            direction,
+            cursor,
+            page_size,
+            user,
+        } => get_history(direction, cursor, page_size, user)
+            .await
+            .context(GetHistory),
+        Commands::SubscribeTransactions { sender, recipient } => {
+            subscribe_transactions(sender, recipient)
+                .await
+                .context(SubscribeTransactions)
+        }
+        Commands::GetStateRoot => get_state_root().await.context(GetStateRoot),
+        Commands::GetPeers => get_peers().await.context(GetPeers),
+        Commands::GetPending => get_pending().await.context(GetPending),
+        Commands::AddNode {
+            public_key,
+            address,
+            rpc_address,
+            sign_key,
+            sequence,
+        } => change_membership(
+            MembershipChange::Add {
+                public_key,
+                address,
+                rpc: rpc_address,
+                sign_key,
+            },
+            sequence,
+        )
+        .await
+        .context(ChangeMembership),
+        Commands::RemoveNode {
+            public_key,
+            sequence,
+        } => change_membership(MembershipChange::Remove { public_key }, sequence)
+            .await
+            .context(ChangeMembership),
     };
 
     if let Err(err) = ret {
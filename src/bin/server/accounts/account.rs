@@ -1,55 +1,112 @@
+use std::collections::BTreeMap;
+
+use at2_node::NATIVE_ASSET;
 use snafu::{ensure, OptionExt};
 
 #[derive(snafu::Snafu, Debug)]
 pub enum Error {
-    InconsecutiveSequence,
+    #[snafu(display("inconsecutive sequence: got {} after {}", sequence, last))]
+    InconsecutiveSequence {
+        last: sieve::Sequence,
+        sequence: sieve::Sequence,
+    },
     Overflow,
     Underflow,
 }
 
-/// Contains the balance and the latest processed sequence for a user
-#[derive(Debug, Clone, Copy)]
+/// Contains the balances and the latest processed sequence for a user
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Account {
     last_sequence: sieve::Sequence,
-    balance: u64,
+    /// Owned amount of each asset, missing if none
+    balances: BTreeMap<String, u64>,
 }
 
-const INITIAL_BALANCE: u64 = 100000;
-
 impl Account {
-    /// Create a new account
+    /// Create a new empty account
     pub fn new() -> Self {
-        Self {
+        Self::with_balance(0)
+    }
+
+    /// Create a new account owning the given amount of [`NATIVE_ASSET`]
+    pub fn with_balance(balance: u64) -> Self {
+        let mut account = Self {
             last_sequence: sieve::Sequence::MIN,
-            balance: INITIAL_BALANCE, // TODO create faucet
+            balances: BTreeMap::new(),
+        };
+        if balance > 0 {
+            account.balances.insert(NATIVE_ASSET.to_owned(), balance);
         }
+
+        account
     }
 
-    /// Add some amount to this account
-    pub fn credit(&mut self, amount: u64) -> Result<(), Error> {
-        self.balance = self.balance.checked_add(amount).context(Overflow)?;
+    /// Add some amount of an asset to this account
+    pub fn credit(&mut self, asset: &str, amount: u64) -> Result<(), Error> {
+        let balance = self.balance(asset).checked_add(amount).context(Overflow)?;
+        self.set_balance(asset, balance);
 
         Ok(())
     }
 
-    /// Remove some amount from this account, iff the `sequence` is consecutive to the last one
-    pub fn debit(&mut self, sequence: sieve::Sequence, amount: u64) -> Result<(), Error> {
-        ensure!(self.last_sequence + 1 == sequence, InconsecutiveSequence);
+    /// Remove the `fee`, in [`NATIVE_ASSET`], and some amount of every given asset from this
+    /// account, iff the `sequence` is consecutive to the last one
+    pub fn debit<'a>(
+        &mut self,
+        sequence: sieve::Sequence,
+        fee: u64,
+        amounts: impl IntoIterator<Item = (&'a str, u64)>,
+    ) -> Result<(), Error> {
+        self.use_sequence(sequence)?;
+
+        self.withdraw(NATIVE_ASSET, fee)?;
+        amounts
+            .into_iter()
+            .try_for_each(|(asset, amount)| self.withdraw(asset, amount))
+    }
+
+    /// Mark the `sequence` as used, iff it is consecutive to the last one
+    pub fn use_sequence(&mut self, sequence: sieve::Sequence) -> Result<(), Error> {
+        ensure!(
+            self.last_sequence + 1 == sequence,
+            InconsecutiveSequence {
+                last: self.last_sequence,
+                sequence
+            }
+        );
         self.last_sequence = sequence;
 
-        self.balance = self.balance.checked_sub(amount).context(Underflow)?;
+        Ok(())
+    }
+
+    fn withdraw(&mut self, asset: &str, amount: u64) -> Result<(), Error> {
+        let balance = self.balance(asset).checked_sub(amount).context(Underflow)?;
+        self.set_balance(asset, balance);
 
         Ok(())
     }
 
+    fn set_balance(&mut self, asset: &str, balance: u64) {
+        if balance == 0 {
+            self.balances.remove(asset);
+        } else {
+            self.balances.insert(asset.to_owned(), balance);
+        }
+    }
+
     /// Return the last used sequence
     pub fn last_sequence(&self) -> sieve::Sequence {
         self.last_sequence
     }
 
-    /// Return the owned amount
-    pub fn balance(&self) -> u64 {
-        self.balance
+    /// Return the owned amount of the given asset
+    pub fn balance(&self, asset: &str) -> u64 {
+        self.balances.get(asset).copied().unwrap_or_default()
+    }
+
+    /// Return the owned amount of every asset, without the empty ones
+    pub fn balances(&self) -> &BTreeMap<String, u64> {
+        &self.balances
     }
 }
 
@@ -59,11 +116,11 @@ mod tests {
 
     #[test]
     fn debit_too_much_fails() {
-        let mut account = Account::new();
+        let mut account = Account::with_balance(10);
 
         let old_seq = account.last_sequence();
         account
-            .debit(1, INITIAL_BALANCE + 1)
+            .debit(1, 0, [(NATIVE_ASSET, 11)])
             .expect_err("able to debit more than possessed");
 
         assert!(old_seq < account.last_sequence());
@@ -71,21 +128,56 @@ mod tests {
 
     #[test]
     fn debit_increase_sequence() {
-        let mut account = Account::new();
+        let mut account = Account::with_balance(10);
 
         let old_seq = account.last_sequence();
-        account.debit(1, 1).expect("to debit account");
+        account
+            .debit(1, 0, [(NATIVE_ASSET, 1)])
+            .expect("to debit account");
 
         assert!(old_seq < account.last_sequence());
     }
 
+    #[test]
+    fn debit_takes_fee_in_native_asset() {
+        let mut account = Account::with_balance(10);
+        account.credit("other", 5).expect("to credit account");
+
+        account
+            .debit(1, 6, [("other", 5)])
+            .expect("to debit account");
+        account
+            .debit(2, 5, [])
+            .expect_err("able to pay more fee than possessed");
+
+        assert_eq!(4, account.balance(NATIVE_ASSET));
+        assert_eq!(0, account.balance("other"));
+    }
+
     #[test]
     fn credit_doesnt_change_sequence() {
         let mut account = Account::new();
 
         let old_seq = account.last_sequence();
-        account.credit(1).expect("to credit account");
+        account.credit(NATIVE_ASSET, 1).expect("to credit account");
 
         assert_eq!(old_seq, account.last_sequence());
     }
+
+    #[test]
+    fn assets_have_separate_balances() {
+        let mut account = Account::with_balance(10);
+
+        account.credit("other", 5).expect("to credit account");
+        account
+            .debit(1, 0, [("other", 6)])
+            .expect_err("able to debit more than possessed");
+        account
+            .debit(2, 0, [("other", 5)])
+            .expect("to debit account");
+
+        assert_eq!(10, account.balance(NATIVE_ASSET));
+        assert_eq!(0, account.balance("other"));
+        assert_eq!(1, account.balances().len());
+    }
 }
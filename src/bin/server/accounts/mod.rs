@@ -1,21 +1,46 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    sync::Arc,
+};
 
+use at2_node::NATIVE_ASSET;
 use drop::crypto::sign;
-use snafu::ResultExt;
+use snafu::{ensure, OptionExt, ResultExt};
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, info, warn};
 
 pub mod account;
 use account::Account;
+pub mod snapshot;
+use snapshot::{Proven, Snapshot};
+pub mod storage;
+use storage::Storage;
 
 #[derive(snafu::Snafu, Debug)]
 pub enum Error {
+    #[snafu(display("no such account: {}", pubkey))]
     NoSuchAccount {
         pubkey: Box<sign::PublicKey>,
     },
+    #[snafu(display("no such receiver: {}", pubkey))]
+    NoSuchReceiver {
+        pubkey: Box<sign::PublicKey>,
+    },
+    #[snafu(display("account already exists: {}", pubkey))]
+    AccountAlreadyExists {
+        pubkey: Box<sign::PublicKey>,
+    },
     AccountModification {
         source: account::Error,
     },
+    #[snafu(display("storage: {}", source))]
+    AccountStorage {
+        source: storage::Error,
+    },
+    #[snafu(display("hash accounts: {}", source))]
+    HashAccounts {
+        source: snapshot::Error,
+    },
 
     #[snafu(display("gone on send"))]
     GoneOnSend,
@@ -25,20 +50,69 @@ pub enum Error {
 
 type Response<T> = oneshot::Sender<Result<T, Error>>;
 
+/// Transfer part of a batch, as receiver, asset and amount
+pub type Output = (sign::PublicKey, String, u64);
+
+/// Paid by the sender of every transfer, in [`NATIVE_ASSET`]
+#[derive(Debug, Clone, Copy)]
+pub struct Fee {
+    /// Account receiving the fees
+    pub treasury: sign::PublicKey,
+    pub amount: u64,
+}
+
 #[derive(Debug)]
 enum Commands {
+    Create {
+        user: Box<sign::PublicKey>,
+        resp: Response<()>,
+    },
     GetBalance {
         user: Box<sign::PublicKey>,
+        asset: String,
         resp: Response<u64>,
     },
+    GetBalances {
+        user: Box<sign::PublicKey>,
+        resp: Response<BTreeMap<String, u64>>,
+    },
     GetLastSequence {
         user: Box<sign::PublicKey>,
-        resp: oneshot::Sender<sieve::Sequence>,
+        resp: Response<sieve::Sequence>,
     },
     Transfer {
         sender: Box<sign::PublicKey>,
         sender_sequence: sieve::Sequence,
         receiver: Box<sign::PublicKey>,
+        asset: String,
+        amount: u64,
+        resp: Response<()>,
+    },
+    TransferBatch {
+        sender: Box<sign::PublicKey>,
+        sender_sequence: sieve::Sequence,
+        outputs: Vec<Output>,
+        resp: Response<()>,
+    },
+    Snapshot {
+        resp: Response<Snapshot>,
+    },
+    Proven {
+        resp: Response<Arc<Proven>>,
+    },
+    Restore {
+        snapshot: Snapshot,
+        resp: Response<()>,
+    },
+    UseSequence {
+        user: Box<sign::PublicKey>,
+        sequence: sieve::Sequence,
+        resp: Response<()>,
+    },
+    Issue {
+        issuer: Box<sign::PublicKey>,
+        issuer_sequence: sieve::Sequence,
+        asset: String,
         amount: u64,
         resp: Response<()>,
     },
@@ -47,26 +121,81 @@ enum Commands {
 #[derive(Clone)]
 pub struct Accounts {
     agent: mpsc::Sender<Commands>,
+    fee: Option<Fee>,
 }
 
 /// Own the accounts themselves
 struct AccountsHandler {
-    ledger: HashMap<sign::PublicKey, account::Account>,
+    ledger: Box<dyn Storage>,
+    fee: Option<Fee>,
+    /// Hashed accounts, dropped on every change
+    proven: Option<Arc<Proven>>,
 }
 
 impl Accounts {
-    pub fn new() -> Self {
-        Self {
-            agent: AccountsHandler::new().spawn(),
-        }
+    /// Create accounts backed by the given storage
+    ///
+    /// The `genesis` accounts are created with their balance if not already stored, as is the
+    /// treasury of the `fee`.
+    pub fn new(
+        storage: impl Storage,
+        genesis: impl IntoIterator<Item = (sign::PublicKey, u64)>,
+        fee: Option<Fee>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            agent: AccountsHandler::new(Box::new(storage), genesis, fee)?.spawn(),
+            fee,
+        })
     }
 
-    /// Return the balance for the given user
-    pub async fn get_balance(&self, user: Box<sign::PublicKey>) -> Result<u64, Error> {
+    /// Return the fee paid for every transfer, if any
+    pub fn fee(&self) -> Option<Fee> {
+        self.fee
+    }
+
+    /// Create an empty account for the given user
+    ///
+    /// It fails if the account already exists.
+    pub async fn create(&self, user: Box<sign::PublicKey>) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
 
         self.agent
-            .send(Commands::GetBalance { user, resp: tx })
+            .send(Commands::Create { user, resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Return the balance of the given asset for the given user
+    pub async fn get_balance(
+        &self,
+        user: Box<sign::PublicKey>,
+        asset: String,
+    ) -> Result<u64, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::GetBalance {
+                user,
+                asset,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Return the balance of every owned asset for the given user
+    pub async fn get_balances(
+        &self,
+        user: Box<sign::PublicKey>,
+    ) -> Result<BTreeMap<String, u64>, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::GetBalances { user, resp: tx })
             .await
             .map_err(|_| Error::GoneOnSend)?;
 
@@ -82,6 +211,7 @@ impl Accounts {
         sender: Box<sign::PublicKey>,
         sender_sequence: sieve::Sequence,
         receiver: Box<sign::PublicKey>,
+        asset: String,
         amount: u64,
     ) -> Result<(), Error> {
         let (tx, rx) = oneshot::channel();
@@ -91,6 +221,58 @@ impl Accounts {
                 sender,
                 sender_sequence,
                 receiver,
+                asset,
+                amount,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Transfer every amount from the `sender` account to its receiver, all or none
+    ///
+    /// It fails if the `sender_sequence` is not consecutive to the last one transfered
+    /// transaction.
+    pub async fn transfer_batch(
+        &self,
+        sender: Box<sign::PublicKey>,
+        sender_sequence: sieve::Sequence,
+        outputs: Vec<Output>,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::TransferBatch {
+                sender,
+                sender_sequence,
+                outputs,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Create an `amount` of `asset` in the `issuer` account
+    ///
+    /// It fails if the `issuer_sequence` is not consecutive to the last one used.
+    pub async fn issue(
+        &self,
+        issuer: Box<sign::PublicKey>,
+        issuer_sequence: sieve::Sequence,
+        asset: String,
+        amount: u64,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Issue {
+                issuer,
+                issuer_sequence,
+                asset,
                 amount,
                 resp: tx,
             })
@@ -100,6 +282,66 @@ impl Accounts {
         rx.await.map_err(|_| Error::GoneOnRecv)?
     }
 
+    /// Return every account
+    pub async fn snapshot(&self) -> Result<Snapshot, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Snapshot { resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Return every account with its Merkle tree, built again only after a change
+    pub async fn proven(&self) -> Result<Arc<Proven>, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Proven { resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Replace the stored accounts by the ones of the snapshot
+    ///
+    /// The accounts not in the snapshot are kept as is.
+    pub async fn restore(&self, snapshot: Snapshot) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Restore { snapshot, resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Consume a `sequence` of the `user` without touching its balances
+    ///
+    /// It fails if the `sequence` is not consecutive to the last one used.
+    pub async fn use_sequence(
+        &self,
+        user: Box<sign::PublicKey>,
+        sequence: sieve::Sequence,
+    ) -> Result<(), Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::UseSequence {
+                user,
+                sequence,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
     /// Return the last sequence used for this user.
     pub async fn get_last_sequence(
         &self,
@@ -112,15 +354,30 @@ impl Accounts {
             .await
             .map_err(|_| Error::GoneOnSend)?;
 
-        Ok(rx.await.map_err(|_| Error::GoneOnRecv)?)
+        rx.await.map_err(|_| Error::GoneOnRecv)?
     }
 }
 
 impl AccountsHandler {
-    fn new() -> Self {
-        Self {
-            ledger: Default::default(),
+    fn new(
+        mut ledger: Box<dyn Storage>,
+        genesis: impl IntoIterator<Item = (sign::PublicKey, u64)>,
+        fee: Option<Fee>,
+    ) -> Result<Self, Error> {
+        let treasury = fee.map(|fee| (fee.treasury, 0));
+        for (user, balance) in genesis.into_iter().chain(treasury) {
+            if ledger.get(&user).is_none() {
+                ledger
+                    .set(user, Account::with_balance(balance))
+                    .context(AccountStorage)?;
+            }
         }
+
+        Ok(Self {
+            ledger,
+            fee,
+            proven: None,
+        })
     }
 
     fn spawn(mut self) -> mpsc::Sender<Commands> {
@@ -129,18 +386,61 @@ impl AccountsHandler {
         tokio::spawn(async move {
             while let Some(cmd) = rx.recv().await {
                 match cmd {
-                    Commands::GetBalance { user, resp } => {
-                        let _ = resp.send(Ok(self.get_balance(&user)));
+                    Commands::Create { user, resp } => {
+                        let _ = resp.send(self.create(*user));
+                    }
+                    Commands::GetBalance { user, asset, resp } => {
+                        let _ = resp.send(self.get_balance(&user, &asset));
+                    }
+                    Commands::GetBalances { user, resp } => {
+                        let _ = resp.send(self.get_balances(&user));
                     }
                     Commands::Transfer {
                         sender,
                         sender_sequence,
                         receiver,
+                        asset,
+                        amount,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.transfer_batch(
+                            *sender,
+                            sender_sequence,
+                            &[(*receiver, asset, amount)],
+                        ));
+                    }
+                    Commands::TransferBatch {
+                        sender,
+                        sender_sequence,
+                        outputs,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.transfer_batch(*sender, sender_sequence, &outputs));
+                    }
+                    Commands::Snapshot { resp } => {
+                        let _ = resp.send(Ok(self.snapshot()));
+                    }
+                    Commands::Proven { resp } => {
+                        let _ = resp.send(self.proven());
+                    }
+                    Commands::Restore { snapshot, resp } => {
+                        let _ = resp.send(self.restore(snapshot));
+                    }
+                    Commands::UseSequence {
+                        user,
+                        sequence,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.use_sequence(*user, sequence));
+                    }
+                    Commands::Issue {
+                        issuer,
+                        issuer_sequence,
+                        asset,
                         amount,
                         resp,
                     } => {
-                        let _ =
-                            resp.send(self.transfer(*sender, sender_sequence, *receiver, amount));
+                        let _ = resp.send(self.issue(*issuer, issuer_sequence, &asset, amount));
                     }
                     Commands::GetLastSequence { user, resp } => {
                         let _ = resp.send(self.get_last_sequence(*user));
@@ -152,69 +452,232 @@ impl AccountsHandler {
         tx
     }
 
-    fn get_balance(&self, user: &sign::PublicKey) -> u64 {
-        // TODO remove me when create_account is done
-        let initial_account = Account::new();
+    fn get_account(&self, user: &sign::PublicKey) -> Result<Account, Error> {
+        self.ledger.get(user).context(NoSuchAccount {
+            pubkey: Box::new(*user),
+        })
+    }
 
-        self.ledger
-            .get(user)
-            .map(|account| account.balance())
-            .unwrap_or_else(|| initial_account.balance())
+    fn create(&mut self, user: sign::PublicKey) -> Result<(), Error> {
+        ensure!(
+            self.ledger.get(&user).is_none(),
+            AccountAlreadyExists {
+                pubkey: Box::new(user)
+            }
+        );
+
+        self.set(user, Account::new())
     }
 
-    fn transfer(
+    fn get_balance(&self, user: &sign::PublicKey, asset: &str) -> Result<u64, Error> {
+        self.get_account(user).map(|account| account.balance(asset))
+    }
+
+    fn get_balances(&self, user: &sign::PublicKey) -> Result<BTreeMap<String, u64>, Error> {
+        self.get_account(user)
+            .map(|account| account.balances().to_owned())
+    }
+
+    fn transfer_batch(
         &mut self,
         sender: sign::PublicKey,
         sender_sequence: sieve::Sequence,
-        receiver: sign::PublicKey,
-        amount: u64,
+        outputs: &[Output],
     ) -> Result<(), Error> {
-        // TODO remove me when create_account is done
-        let initial_account = Account::new();
+        let sender_account = self.get_account(&sender)?;
+
+        // fetch every receiver first, failing the whole transfer if one is missing
+        let mut updated = HashMap::with_capacity(outputs.len() + 2);
+        updated.insert(sender, sender_account.clone());
+        if let Some(fee) = self.fee {
+            if let Entry::Vacant(entry) = updated.entry(fee.treasury) {
+                entry.insert(self.get_account(&fee.treasury)?);
+            }
+        }
+        for (receiver, _, _) in outputs {
+            if let Entry::Vacant(entry) = updated.entry(*receiver) {
+                match self.ledger.get(receiver) {
+                    Some(account) => {
+                        entry.insert(account);
+                    }
+                    None => {
+                        self.consume_sequence(sender, sender_account, sender_sequence)?;
 
-        if sender.eq(&receiver) {
+                        return NoSuchReceiver {
+                            pubkey: Box::new(*receiver),
+                        }
+                        .fail();
+                    }
+                }
+            }
+        }
+
+        if outputs.iter().any(|(receiver, _, _)| *receiver == sender) {
             warn!(?sender, "transfer to itself");
+        }
 
-            let account = self.ledger.entry(sender).or_insert(initial_account);
+        debug!(?sender_account, ?outputs, "before transfer");
 
-            account
-                .debit(sender_sequence, 0)
-                .context(AccountModification)?;
-        } else {
-            let mut sender_account = *self.ledger.get(&sender).unwrap_or(&initial_account);
-            let mut receiver_account = *self.ledger.get(&receiver).unwrap_or(&initial_account);
+        let res = Self::apply_outputs(&mut updated, sender, sender_sequence, self.fee, outputs);
 
-            debug!(?sender_account, ?receiver_account, "before transfer");
+        if let Err(err) = res {
+            self.consume_sequence(sender, sender_account, sender_sequence)?;
 
-            let sender_res = sender_account
-                .debit(sender_sequence, amount)
-                .context(AccountModification);
-            self.ledger.insert(sender, sender_account);
+            return Err(err).context(AccountModification);
+        }
 
-            sender_res?;
+        // at once, so that a crash can't lose part of the transfer
+        self.set_all(updated.into_iter().collect())?;
 
-            receiver_account
-                .credit(amount)
-                .context(AccountModification)?;
-            self.ledger.insert(receiver, receiver_account);
+        info!(?sender, ?outputs, "after transfer");
 
-            info!(?sender_account, ?receiver_account, "after transfer");
+        Ok(())
+    }
+
+    /// Only consume the sequence of a failed transfer, keeping every balance
+    fn consume_sequence(
+        &mut self,
+        sender: sign::PublicKey,
+        mut sender_account: Account,
+        sender_sequence: sieve::Sequence,
+    ) -> Result<(), Error> {
+        if sender_account.use_sequence(sender_sequence).is_ok() {
+            self.set(sender, sender_account)?;
         }
 
         Ok(())
     }
 
-    fn get_last_sequence(&self, sender: sign::PublicKey) -> sieve::Sequence {
-        if let Some(sender_account) = self.ledger.get(&sender) {
-            sender_account.last_sequence()
-        } else {
-            0
+    /// Debit the sender then credit every receiver and the treasury, in the given accounts
+    fn apply_outputs(
+        accounts: &mut HashMap<sign::PublicKey, Account>,
+        sender: sign::PublicKey,
+        sender_sequence: sieve::Sequence,
+        fee: Option<Fee>,
+        outputs: &[Output],
+    ) -> Result<(), account::Error> {
+        // transfers to itself are not moving any asset
+        let outputs = outputs
+            .iter()
+            .filter(|(receiver, _, _)| *receiver != sender)
+            .collect::<Vec<_>>();
+
+        let mut totals = BTreeMap::<&str, u64>::new();
+        for (_, asset, amount) in outputs.iter() {
+            let total = totals.entry(asset).or_default();
+            *total = total
+                .checked_add(*amount)
+                // more than representable, thus more than owned
+                .ok_or(account::Error::Underflow)?;
         }
+
+        accounts
+            .get_mut(&sender)
+            .expect("sender to be fetched")
+            .debit(
+                sender_sequence,
+                fee.map(|fee| fee.amount).unwrap_or_default(),
+                totals,
+            )?;
+
+        if let Some(fee) = fee {
+            accounts
+                .get_mut(&fee.treasury)
+                .expect("treasury to be fetched")
+                .credit(NATIVE_ASSET, fee.amount)?;
+        }
+
+        for (receiver, asset, amount) in outputs {
+            accounts
+                .get_mut(receiver)
+                .expect("receiver to be fetched")
+                .credit(asset, *amount)?;
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, user: sign::PublicKey, account: Account) -> Result<(), Error> {
+        self.proven = None;
+
+        self.ledger.set(user, account).context(AccountStorage)
+    }
+
+    fn set_all(&mut self, accounts: Vec<(sign::PublicKey, Account)>) -> Result<(), Error> {
+        self.proven = None;
+
+        self.ledger.set_all(accounts).context(AccountStorage)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.ledger.all())
+    }
+
+    fn proven(&mut self) -> Result<Arc<Proven>, Error> {
+        if let Some(proven) = &self.proven {
+            return Ok(proven.clone());
+        }
+
+        let proven = Arc::new(Proven::new(self.snapshot()).context(HashAccounts)?);
+        self.proven = Some(proven.clone());
+
+        Ok(proven)
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        let restored = snapshot.accounts().len();
+
+        self.set_all(snapshot.into_accounts())?;
+        info!(restored, "accounts restored");
+
+        Ok(())
+    }
+
+    fn use_sequence(
+        &mut self,
+        user: sign::PublicKey,
+        sequence: sieve::Sequence,
+    ) -> Result<(), Error> {
+        let mut account = self.get_account(&user)?;
+
+        account
+            .use_sequence(sequence)
+            .context(AccountModification)?;
+        self.set(user, account)
+    }
+
+    fn issue(
+        &mut self,
+        issuer: sign::PublicKey,
+        issuer_sequence: sieve::Sequence,
+        asset: &str,
+        amount: u64,
+    ) -> Result<(), Error> {
+        let mut account = self.get_account(&issuer)?;
+
+        account
+            .use_sequence(issuer_sequence)
+            .context(AccountModification)?;
+        // sequence is used even if failing to issue
+        let res = account.credit(asset, amount).context(AccountModification);
+        self.set(issuer, account)?;
+
+        res?;
+        info!(?issuer, asset, amount, "issued");
+
+        Ok(())
+    }
+
+    fn get_last_sequence(&self, sender: sign::PublicKey) -> Result<sieve::Sequence, Error> {
+        self.get_account(&sender)
+            .map(|sender_account| sender_account.last_sequence())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::iter;
+
     use super::*;
 
     async fn get_balance_and_sequence(
@@ -223,7 +686,7 @@ mod tests {
     ) -> (u64, sieve::Sequence) {
         (
             accounts
-                .get_balance(user_pubkey.clone())
+                .get_balance(user_pubkey.clone(), NATIVE_ASSET.to_owned())
                 .await
                 .expect("to get balance"),
             accounts
@@ -233,29 +696,84 @@ mod tests {
         )
     }
 
+    async fn create_account(accounts: &Accounts) -> Box<sign::PublicKey> {
+        let user_pubkey = Box::new(sign::KeyPair::random().public());
+
+        accounts
+            .create(user_pubkey.clone())
+            .await
+            .expect("to create account");
+
+        user_pubkey
+    }
+
     #[tokio::test]
-    async fn new_account_is_the_same_as_unknown_account() {
-        let accounts = Accounts::new();
+    async fn unknown_account_is_not_found() {
+        let accounts = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
         let user_pubkey = Box::new(sign::KeyPair::random().public());
 
+        assert!(matches!(
+            accounts
+                .get_balance(user_pubkey.clone(), NATIVE_ASSET.to_owned())
+                .await,
+            Err(Error::NoSuchAccount { .. })
+        ));
+        assert!(matches!(
+            accounts.get_last_sequence(user_pubkey).await,
+            Err(Error::NoSuchAccount { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn created_account_is_the_same_as_new_account() {
+        let accounts = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
+        let user_pubkey = create_account(&accounts).await;
+
         let new_account = Account::new();
 
         let (balance, sequence) = get_balance_and_sequence(&accounts, user_pubkey).await;
 
-        assert_eq!(balance, new_account.balance(),);
+        assert_eq!(balance, new_account.balance(NATIVE_ASSET),);
         assert_eq!(sequence, new_account.last_sequence(),);
     }
 
+    #[tokio::test]
+    async fn genesis_account_has_its_balance() {
+        const BALANCE: u64 = 100;
+
+        let user_pubkey = sign::KeyPair::random().public();
+        let accounts = Accounts::new(
+            storage::Memory::default(),
+            iter::once((user_pubkey, BALANCE)),
+            None,
+        )
+        .expect("to create accounts");
+
+        let (balance, sequence) = get_balance_and_sequence(&accounts, Box::new(user_pubkey)).await;
+
+        assert_eq!(balance, BALANCE);
+        assert_eq!(sequence, Account::new().last_sequence());
+    }
+
     #[tokio::test]
     async fn transfer_to_themselves_increment_sequence_and_keep_balance() {
-        let accounts = Accounts::new();
-        let user_pubkey = Box::new(sign::KeyPair::random().public());
+        let accounts = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
+        let user_pubkey = create_account(&accounts).await;
 
         let (initial_balance, initial_sequence) =
             get_balance_and_sequence(&accounts, user_pubkey.clone()).await;
 
         accounts
-            .transfer(user_pubkey.clone(), 1, user_pubkey.clone(), 10)
+            .transfer(
+                user_pubkey.clone(),
+                1,
+                user_pubkey.clone(),
+                NATIVE_ASSET.to_owned(),
+                10,
+            )
             .await
             .expect("to transfer to themselves");
 
@@ -268,9 +786,10 @@ mod tests {
 
     #[tokio::test]
     async fn transfer_too_much_fails_and_increases_sequence() {
-        let accounts = Accounts::new();
-        let first_user_pubkey = Box::new(sign::KeyPair::random().public());
-        let second_user_pubkey = Box::new(sign::KeyPair::random().public());
+        let accounts = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
+        let first_user_pubkey = create_account(&accounts).await;
+        let second_user_pubkey = create_account(&accounts).await;
 
         let (first_initial_balance, first_initial_sequence) =
             get_balance_and_sequence(&accounts, first_user_pubkey.clone()).await;
@@ -282,6 +801,7 @@ mod tests {
                 first_user_pubkey.clone(),
                 1,
                 second_user_pubkey.clone(),
+                NATIVE_ASSET.to_owned(),
                 first_initial_balance + 1,
             )
             .await
@@ -298,4 +818,272 @@ mod tests {
         assert_eq!(second_initial_balance, second_final_balance);
         assert_eq!(second_initial_sequence, second_final_sequence,);
     }
+
+    #[tokio::test]
+    async fn transfer_to_unknown_fails_and_increases_sequence() {
+        const BALANCE: u64 = 10;
+
+        let sender = sign::KeyPair::random().public();
+        let accounts = Accounts::new(
+            storage::Memory::default(),
+            iter::once((sender, BALANCE)),
+            None,
+        )
+        .expect("to create accounts");
+
+        assert!(matches!(
+            accounts
+                .transfer(
+                    Box::new(sender),
+                    1,
+                    Box::new(sign::KeyPair::random().public()),
+                    NATIVE_ASSET.to_owned(),
+                    1,
+                )
+                .await,
+            Err(Error::NoSuchReceiver { .. })
+        ));
+
+        assert_eq!(
+            get_balance_and_sequence(&accounts, Box::new(sender)).await,
+            (BALANCE, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_transfer_is_all_or_none() {
+        const BALANCE: u64 = 10;
+
+        let sender = sign::KeyPair::random().public();
+        let accounts = Accounts::new(
+            storage::Memory::default(),
+            iter::once((sender, BALANCE)),
+            None,
+        )
+        .expect("to create accounts");
+        let first_receiver = create_account(&accounts).await;
+        let second_receiver = create_account(&accounts).await;
+
+        accounts
+            .transfer_batch(
+                Box::new(sender),
+                1,
+                vec![
+                    (*first_receiver, NATIVE_ASSET.to_owned(), 4),
+                    (*second_receiver, NATIVE_ASSET.to_owned(), BALANCE),
+                ],
+            )
+            .await
+            .expect_err("to fail to transfer more than owned");
+
+        assert_eq!(
+            (BALANCE, 1),
+            get_balance_and_sequence(&accounts, Box::new(sender)).await
+        );
+        assert_eq!(
+            0,
+            accounts
+                .get_balance(first_receiver.clone(), NATIVE_ASSET.to_owned())
+                .await
+                .expect("to get balance")
+        );
+
+        accounts
+            .transfer_batch(
+                Box::new(sender),
+                2,
+                vec![
+                    (*first_receiver, NATIVE_ASSET.to_owned(), 4),
+                    (*second_receiver, NATIVE_ASSET.to_owned(), 6),
+                ],
+            )
+            .await
+            .expect("to transfer batch");
+
+        assert_eq!(
+            (0, 2),
+            get_balance_and_sequence(&accounts, Box::new(sender)).await
+        );
+        assert_eq!(
+            4,
+            accounts
+                .get_balance(first_receiver, NATIVE_ASSET.to_owned())
+                .await
+                .expect("to get balance")
+        );
+        assert_eq!(
+            6,
+            accounts
+                .get_balance(second_receiver, NATIVE_ASSET.to_owned())
+                .await
+                .expect("to get balance")
+        );
+    }
+
+    #[tokio::test]
+    async fn issued_asset_is_only_credited_to_issuer() {
+        let accounts = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
+        let issuer = create_account(&accounts).await;
+
+        accounts
+            .issue(issuer.clone(), 1, "token".to_owned(), 10)
+            .await
+            .expect("to issue asset");
+
+        assert_eq!(
+            (0, 1),
+            get_balance_and_sequence(&accounts, issuer.clone()).await
+        );
+        assert_eq!(
+            10,
+            accounts
+                .get_balance(issuer.clone(), "token".to_owned())
+                .await
+                .expect("to get balance")
+        );
+        assert_eq!(
+            vec![("token".to_owned(), 10)],
+            accounts
+                .get_balances(issuer)
+                .await
+                .expect("to get balances")
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn transfer_pays_fee_to_treasury() {
+        const BALANCE: u64 = 10;
+        let fee = Fee {
+            treasury: sign::KeyPair::random().public(),
+            amount: 2,
+        };
+
+        let sender = sign::KeyPair::random().public();
+        let accounts = Accounts::new(
+            storage::Memory::default(),
+            iter::once((sender, BALANCE)),
+            Some(fee),
+        )
+        .expect("to create accounts");
+        let receiver = create_account(&accounts).await;
+
+        accounts
+            .transfer(
+                Box::new(sender),
+                1,
+                receiver.clone(),
+                NATIVE_ASSET.to_owned(),
+                BALANCE - 1,
+            )
+            .await
+            .expect_err("to fail to pay the fee");
+        accounts
+            .transfer(
+                Box::new(sender),
+                2,
+                receiver.clone(),
+                NATIVE_ASSET.to_owned(),
+                BALANCE - fee.amount,
+            )
+            .await
+            .expect("to transfer with fee");
+
+        assert_eq!(
+            (0, 2),
+            get_balance_and_sequence(&accounts, Box::new(sender)).await
+        );
+        assert_eq!(
+            (BALANCE - fee.amount, 0),
+            get_balance_and_sequence(&accounts, receiver).await
+        );
+        assert_eq!(
+            (fee.amount, 0),
+            get_balance_and_sequence(&accounts, Box::new(fee.treasury)).await
+        );
+    }
+
+    #[tokio::test]
+    async fn used_sequence_keeps_balance() {
+        let user = sign::KeyPair::random().public();
+        let accounts = Accounts::new(storage::Memory::default(), iter::once((user, 10)), None)
+            .expect("to create accounts");
+
+        accounts
+            .use_sequence(Box::new(user), 1)
+            .await
+            .expect("to use sequence");
+        accounts
+            .use_sequence(Box::new(user), 3)
+            .await
+            .expect_err("able to skip a sequence");
+
+        assert_eq!(
+            (10, 1),
+            get_balance_and_sequence(&accounts, Box::new(user)).await
+        );
+    }
+
+    #[tokio::test]
+    async fn restored_snapshot_gives_same_accounts() {
+        let user = sign::KeyPair::random().public();
+        let source = Accounts::new(storage::Memory::default(), iter::once((user, 10)), None)
+            .expect("to create accounts");
+        let receiver = create_account(&source).await;
+        source
+            .transfer(
+                Box::new(user),
+                1,
+                receiver.clone(),
+                NATIVE_ASSET.to_owned(),
+                3,
+            )
+            .await
+            .expect("to transfer");
+
+        let snapshot = source.snapshot().await.expect("to snapshot accounts");
+        let restored = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
+        restored
+            .restore(snapshot)
+            .await
+            .expect("to restore accounts");
+
+        assert_eq!(
+            (7, 1),
+            get_balance_and_sequence(&restored, Box::new(user)).await
+        );
+        assert_eq!((3, 0), get_balance_and_sequence(&restored, receiver).await);
+    }
+
+    #[tokio::test]
+    async fn proven_accounts_follow_changes() {
+        let accounts = Accounts::new(storage::Memory::default(), iter::empty(), None)
+            .expect("to create accounts");
+        let user = create_account(&accounts).await;
+
+        let proven = accounts.proven().await.expect("to prove accounts");
+        assert!(Arc::ptr_eq(
+            &proven,
+            &accounts.proven().await.expect("to prove accounts")
+        ));
+
+        accounts
+            .use_sequence(user.clone(), 1)
+            .await
+            .expect("to use sequence");
+        let changed = accounts.proven().await.expect("to prove accounts");
+        assert_ne!(proven.state_root(), changed.state_root());
+        assert_eq!(
+            accounts
+                .snapshot()
+                .await
+                .expect("to snapshot accounts")
+                .state_root()
+                .expect("to hash snapshot"),
+            changed.state_root()
+        );
+    }
 }
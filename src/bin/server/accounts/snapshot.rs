@@ -0,0 +1,194 @@
+use at2_node::state::{self, Digest};
+use drop::crypto::sign;
+use snafu::{ensure, ResultExt, Snafu};
+
+use super::account::Account;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("encode: {}", source))]
+    Encode { source: bincode::Error },
+    #[snafu(display("decode: {}", source))]
+    Decode { source: bincode::Error },
+    #[snafu(display("accounts not sorted by user at {}", user))]
+    Unsorted { user: sign::PublicKey },
+}
+
+/// Every account, sorted by user, in a form shared by every node agreeing on them
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot(Vec<(sign::PublicKey, Account)>);
+
+/// Snapshot with its Merkle tree, built once for every proof until the accounts change
+#[derive(Debug)]
+pub struct Proven {
+    snapshot: Snapshot,
+    tree: state::Tree,
+}
+
+impl Snapshot {
+    /// Sort the given accounts, keeping the last one given for a user
+    pub fn new(accounts: impl IntoIterator<Item = (sign::PublicKey, Account)>) -> Self {
+        let mut accounts = accounts.into_iter().collect::<Vec<_>>();
+        accounts.reverse();
+        accounts.sort_by_key(|(user, _)| *user);
+        accounts.dedup_by_key(|(user, _)| *user);
+
+        Self(accounts)
+    }
+
+    /// Canonical encoding, the same for the same accounts
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        bincode::serialize(&self.0).context(Encode)
+    }
+
+    /// Decode a snapshot, failing if not canonical
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let accounts: Vec<(sign::PublicKey, Account)> =
+            bincode::deserialize(bytes).context(Decode)?;
+
+        for pair in accounts.windows(2) {
+            ensure!(pair[0].0 < pair[1].0, Unsorted { user: pair[1].0 });
+        }
+
+        Ok(Self(accounts))
+    }
+
+    /// Root of the Merkle tree over the accounts
+    pub fn state_root(&self) -> Result<Digest, Error> {
+        Ok(state::state_root(&self.hashes()?))
+    }
+
+    fn hashes(&self) -> Result<Vec<Digest>, Error> {
+        self.0
+            .iter()
+            .map(|(user, account)| {
+                state::account_hash(user, account.last_sequence(), account.balances())
+            })
+            .collect::<Result<_, _>>()
+            .context(Encode)
+    }
+
+    /// Number of operations applied to the accounts, the same for the same accounts
+    ///
+    /// Each account counts every sequence it used, its creation included.
+    pub fn height(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|(_, account)| u64::from(account.last_sequence()) + 1)
+            .sum()
+    }
+
+    pub fn accounts(&self) -> &[(sign::PublicKey, Account)] {
+        &self.0
+    }
+
+    pub fn into_accounts(self) -> Vec<(sign::PublicKey, Account)> {
+        self.0
+    }
+}
+
+impl Proven {
+    /// Hash every account of the snapshot
+    pub fn new(snapshot: Snapshot) -> Result<Self, Error> {
+        let tree = state::Tree::new(snapshot.hashes()?);
+
+        Ok(Self { snapshot, tree })
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    pub fn state_root(&self) -> Digest {
+        self.tree.root()
+    }
+
+    /// Account of the user with the proof that it is part of the returned state root
+    pub fn prove(&self, user: &sign::PublicKey) -> Option<(&Account, state::Proof, Digest)> {
+        let index = self
+            .snapshot
+            .0
+            .binary_search_by_key(user, |(user, _)| *user)
+            .ok()?;
+
+        self.tree
+            .prove(index)
+            .map(|proof| (&self.snapshot.0[index].1, proof, self.tree.root()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_is_canonical() {
+        let accounts = (0..10)
+            .map(|balance| {
+                (
+                    sign::KeyPair::random().public(),
+                    Account::with_balance(balance),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let snapshot = Snapshot::new(accounts.clone());
+        let reversed = Snapshot::new(accounts.into_iter().rev());
+
+        let encoded = snapshot.encode().expect("to encode snapshot");
+        assert_eq!(encoded, reversed.encode().expect("to encode snapshot"));
+        assert_eq!(
+            snapshot.state_root().expect("to hash snapshot"),
+            Snapshot::decode(&encoded)
+                .expect("to decode snapshot")
+                .state_root()
+                .expect("to hash snapshot")
+        );
+    }
+
+    #[test]
+    fn proven_account_leads_to_state_root() {
+        let users = (0..5)
+            .map(|_| sign::KeyPair::random().public())
+            .collect::<Vec<_>>();
+        let snapshot = Snapshot::new(
+            users
+                .iter()
+                .enumerate()
+                .map(|(balance, user)| (*user, Account::with_balance(balance as u64))),
+        );
+
+        let state_root = snapshot.state_root().expect("to hash snapshot");
+        let proven = Proven::new(snapshot).expect("to hash snapshot");
+        assert_eq!(state_root, proven.state_root());
+        for user in users.iter() {
+            let (account, proof, root) = proven.prove(user).expect("to find account");
+
+            assert_eq!(state_root, root);
+            assert_eq!(
+                root,
+                proof.root(
+                    state::account_hash(user, account.last_sequence(), account.balances())
+                        .expect("to hash account")
+                )
+            );
+        }
+
+        assert!(proven.prove(&sign::KeyPair::random().public()).is_none());
+    }
+
+    #[test]
+    fn unsorted_snapshot_is_rejected() {
+        let mut accounts = Snapshot::new((0..2).map(|balance| {
+            (
+                sign::KeyPair::random().public(),
+                Account::with_balance(balance),
+            )
+        }))
+        .into_accounts();
+        accounts.reverse();
+
+        Snapshot::decode(&bincode::serialize(&accounts).expect("to encode accounts"))
+            .expect_err("to reject unsorted accounts");
+    }
+}
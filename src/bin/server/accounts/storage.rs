@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use drop::crypto::sign;
+use snafu::{ResultExt, Snafu};
+
+use super::account::Account;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("open {:?}: {}", path, source))]
+    Open { path: PathBuf, source: io::Error },
+    #[snafu(display("read: {}", source))]
+    Read { source: io::Error },
+    #[snafu(display("decode: {}", source))]
+    Decode { source: bincode::Error },
+    #[snafu(display("encode: {}", source))]
+    Encode { source: bincode::Error },
+    #[snafu(display("write: {}", source))]
+    Write { source: io::Error },
+}
+
+/// Backend keeping the accounts of [`super::AccountsHandler`]
+pub trait Storage: Send + 'static {
+    /// Return the account of the given user, if any
+    fn get(&self, user: &sign::PublicKey) -> Option<Account>;
+
+    /// Store the given accounts at once, replacing the previous ones
+    ///
+    /// Either every account is stored or none, even if crashing meanwhile.
+    fn set_all(&mut self, accounts: Vec<(sign::PublicKey, Account)>) -> Result<(), Error>;
+
+    /// Store the given account, replacing the previous one
+    fn set(&mut self, user: sign::PublicKey, account: Account) -> Result<(), Error> {
+        self.set_all(vec![(user, account)])
+    }
+
+    /// Return every stored account, in no particular order
+    fn all(&self) -> Vec<(sign::PublicKey, Account)>;
+}
+
+/// Keep accounts in memory, they are lost on restart
+#[derive(Default)]
+pub struct Memory(HashMap<sign::PublicKey, Account>);
+
+impl Storage for Memory {
+    fn get(&self, user: &sign::PublicKey) -> Option<Account> {
+        self.0.get(user).cloned()
+    }
+
+    fn set_all(&mut self, accounts: Vec<(sign::PublicKey, Account)>) -> Result<(), Error> {
+        self.0.extend(accounts);
+
+        Ok(())
+    }
+
+    fn all(&self) -> Vec<(sign::PublicKey, Account)> {
+        self.0
+            .iter()
+            .map(|(user, account)| (*user, account.clone()))
+            .collect()
+    }
+}
+
+/// Append every account modification to a file, replayed on open
+///
+/// Each record holds every account modified at once, so that a partially written one, when
+/// crashing while appending, is ignored as a whole.
+pub struct File {
+    accounts: Memory,
+    file: fs::File,
+}
+
+impl File {
+    /// Open or create the file at the given path and load the accounts it contains
+    ///
+    /// The file is compacted to only contain the latest version of each account.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut accounts = Memory::default();
+
+        match fs::read(path) {
+            Ok(content) => {
+                let mut reader = io::Cursor::new(content);
+                while reader.position() < reader.get_ref().len() as u64 {
+                    match bincode::deserialize_from::<_, Vec<(sign::PublicKey, Account)>>(
+                        &mut reader,
+                    ) {
+                        Ok(record) => accounts.0.extend(record),
+                        Err(err) => match *err {
+                            // partially written record, crashed while appending
+                            bincode::ErrorKind::Io(ref err)
+                                if err.kind() == io::ErrorKind::UnexpectedEof =>
+                            {
+                                break
+                            }
+                            _ => return Err(err).context(Decode),
+                        },
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(Read),
+        }
+
+        let compacted_path = path.with_extension("compact");
+        let mut compacted = fs::File::create(&compacted_path).context(Open {
+            path: compacted_path.clone(),
+        })?;
+        compacted
+            .write_all(&bincode::serialize(&accounts.all()).context(Encode)?)
+            .context(Write)?;
+        compacted.sync_all().context(Write)?;
+        fs::rename(&compacted_path, path).context(Write)?;
+
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .context(Open { path })?;
+
+        Ok(Self { accounts, file })
+    }
+}
+
+impl Storage for File {
+    fn get(&self, user: &sign::PublicKey) -> Option<Account> {
+        self.accounts.get(user)
+    }
+
+    fn set_all(&mut self, accounts: Vec<(sign::PublicKey, Account)>) -> Result<(), Error> {
+        let record = bincode::serialize(&accounts).context(Encode)?;
+
+        self.file.write_all(&record).context(Write)?;
+        self.file.sync_data().context(Write)?;
+
+        self.accounts.set_all(accounts)
+    }
+
+    fn all(&self) -> Vec<(sign::PublicKey, Account)> {
+        self.accounts.all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use at2_node::NATIVE_ASSET;
+
+    use super::*;
+
+    #[test]
+    fn file_keeps_accounts_across_reopen() {
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("accounts");
+        let user = sign::KeyPair::random().public();
+
+        let mut account = Account::with_balance(100);
+        account
+            .debit(1, 0, [(NATIVE_ASSET, 10)])
+            .expect("to debit account");
+
+        {
+            let mut storage = File::open(&path).expect("to open storage");
+            storage.set(user, Account::new()).expect("to store account");
+            storage
+                .set(user, account.clone())
+                .expect("to store account");
+        }
+
+        let reopened = File::open(&path)
+            .expect("to reopen storage")
+            .get(&user)
+            .expect("to find stored account");
+
+        assert_eq!(account.balances(), reopened.balances());
+        assert_eq!(account.last_sequence(), reopened.last_sequence());
+    }
+
+    #[test]
+    fn partially_written_accounts_are_all_ignored() {
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("accounts");
+        let sender = sign::KeyPair::random().public();
+        let receiver = sign::KeyPair::random().public();
+
+        {
+            let mut storage = File::open(&path).expect("to open storage");
+            storage
+                .set_all(vec![
+                    (sender, Account::with_balance(10)),
+                    (receiver, Account::new()),
+                ])
+                .expect("to store accounts");
+            storage
+                .set_all(vec![
+                    (sender, Account::with_balance(5)),
+                    (receiver, Account::with_balance(5)),
+                ])
+                .expect("to store accounts");
+        }
+
+        // crash while appending the last record
+        let content = fs::read(&path).expect("to read storage");
+        let last_record = bincode::serialize(&vec![
+            (sender, Account::with_balance(5)),
+            (receiver, Account::with_balance(5)),
+        ])
+        .expect("to encode record");
+        fs::write(&path, &content[..content.len() - last_record.len() / 2])
+            .expect("to truncate storage");
+
+        let reopened = File::open(&path).expect("to reopen storage");
+        assert_eq!(
+            10,
+            reopened
+                .get(&sender)
+                .expect("to find sender")
+                .balance(NATIVE_ASSET)
+        );
+        assert_eq!(
+            0,
+            reopened
+                .get(&receiver)
+                .expect("to find receiver")
+                .balance(NATIVE_ASSET)
+        );
+    }
+}
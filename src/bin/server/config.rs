@@ -1,7 +1,7 @@
-use std::io;
+use std::{io, path::PathBuf};
 
 use drop::crypto::{key::exchange, sign};
-use snafu::{ResultExt, Snafu};
+use snafu::{ensure, ResultExt, Snafu};
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct ConfigAddresses {
@@ -16,20 +16,247 @@ pub struct ConfigKeys {
     pub network: exchange::PrivateKey,
 }
 
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigStorage {
+    pub directory: PathBuf,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct GenesisAccount {
+    #[serde(with = "hex")]
+    pub public_key: sign::PublicKey,
+    pub balance: u64,
+}
+
+/// Accounts existing when the network starts, should be the same for every node
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigGenesis {
+    /// Account distributing the asset to the users
+    pub faucet: GenesisAccount,
+    #[serde(skip_serializing_if = "Vec::is_empty", default = "Vec::default")]
+    pub accounts: Vec<GenesisAccount>,
+}
+
+impl ConfigGenesis {
+    /// Iterate over every account, faucet included
+    pub fn accounts(&self) -> impl Iterator<Item = &GenesisAccount> {
+        std::iter::once(&self.faucet).chain(self.accounts.iter())
+    }
+}
+
+/// Assets other than the native one, should be the same for every node
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigAssets {
+    /// Only account allowed to issue new assets
+    #[serde(with = "hex")]
+    pub issuer: sign::PublicKey,
+}
+
+/// Paid by the sender of every transfer, should be the same for every node
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigFee {
+    /// Account receiving the fees
+    #[serde(with = "hex")]
+    pub treasury: sign::PublicKey,
+    /// Amount of native asset to pay
+    pub amount: u64,
+}
+
+/// Administration of the network, should be the same for every node
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ConfigAdmin {
+    /// Only account allowed to change the nodes of the network
+    #[serde(with = "hex")]
+    pub public_key: sign::PublicKey,
+}
+
+/// How the nodes are chosen to exchange with
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sampling {
+    /// Every node of the network, the sample sizes are ignored
+    #[default]
+    All,
+    /// Random nodes, as many as the sample sizes, for large networks
+    Poisson,
+}
+
+/// Parameters of the broadcast, every one not given is derived from the fault tolerance
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+pub struct ConfigConsensus {
+    /// Number of faulty nodes to tolerate, a third of the network if not given
+    pub fault_tolerance: Option<usize>,
+    #[serde(default)]
+    pub sampling: Sampling,
+    pub sieve_sample_size: Option<usize>,
+    pub echo_threshold: Option<usize>,
+    pub murmur_gossip_size: Option<usize>,
+    pub contagion_sample_size: Option<usize>,
+    pub ready_threshold: Option<usize>,
+}
+
+/// Parameters of the broadcast, resolved against the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsensusParameters {
+    pub fault_tolerance: usize,
+    pub sampling: Sampling,
+    pub sieve_sample_size: usize,
+    pub echo_threshold: usize,
+    pub murmur_gossip_size: usize,
+    pub contagion_sample_size: usize,
+    pub ready_threshold: usize,
+}
+
+impl ConfigConsensus {
+    /// Fill the missing parameters for a network of `network_size` other nodes, then check them
+    ///
+    /// By default, every node is sampled and all but the faulty ones have to agree. The network,
+    /// this node included, has to hold more than three times the faulty nodes, and when sampling
+    /// every node, the thresholds can't be lower than a quorum of it.
+    pub fn resolve(&self, network_size: usize) -> Result<ConsensusParameters, Error> {
+        let nodes = network_size + 1;
+        let fault_tolerance = self.fault_tolerance.unwrap_or(network_size / 3);
+        ensure!(
+            nodes > 3 * fault_tolerance,
+            InvalidConsensus {
+                reason: format!("fault tolerance of {} for {} nodes", fault_tolerance, nodes)
+            }
+        );
+        let threshold = network_size - fault_tolerance;
+
+        let parameters = ConsensusParameters {
+            fault_tolerance,
+            sampling: self.sampling,
+            sieve_sample_size: self.sieve_sample_size.unwrap_or(network_size),
+            echo_threshold: self.echo_threshold.unwrap_or(threshold),
+            murmur_gossip_size: self.murmur_gossip_size.unwrap_or(network_size),
+            contagion_sample_size: self.contagion_sample_size.unwrap_or(network_size),
+            ready_threshold: self.ready_threshold.unwrap_or(threshold),
+        };
+
+        for (name, size) in [
+            ("sieve_sample_size", parameters.sieve_sample_size),
+            ("murmur_gossip_size", parameters.murmur_gossip_size),
+            ("contagion_sample_size", parameters.contagion_sample_size),
+        ] {
+            ensure!(
+                size <= network_size,
+                InvalidConsensus {
+                    reason: format!("{} of {} for {} nodes", name, size, network_size)
+                }
+            );
+        }
+
+        for (name, threshold, sample_size) in [
+            (
+                "echo_threshold",
+                parameters.echo_threshold,
+                parameters.sieve_sample_size,
+            ),
+            (
+                "ready_threshold",
+                parameters.ready_threshold,
+                parameters.contagion_sample_size,
+            ),
+        ] {
+            ensure!(
+                threshold <= sample_size,
+                InvalidConsensus {
+                    reason: format!("{} of {} for a sample of {}", name, threshold, sample_size)
+                }
+            );
+            ensure!(
+                threshold > 0 || sample_size == 0,
+                InvalidConsensus {
+                    reason: format!("{} of zero", name)
+                }
+            );
+        }
+
+        // counting the other nodes, this one agreeing with itself
+        if parameters.sampling == Sampling::All {
+            for (name, threshold, quorum) in [
+                (
+                    "echo_threshold",
+                    parameters.echo_threshold,
+                    (nodes + fault_tolerance) / 2,
+                ),
+                (
+                    "ready_threshold",
+                    parameters.ready_threshold,
+                    2 * fault_tolerance,
+                ),
+            ] {
+                ensure!(
+                    threshold >= quorum,
+                    InvalidConsensus {
+                        reason: format!("{} of {} below the quorum of {}", name, threshold, quorum)
+                    }
+                );
+            }
+        }
+
+        Ok(parameters)
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Config {
     pub addresses: ConfigAddresses,
     pub keys: ConfigKeys,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub storage: Option<ConfigStorage>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub genesis: Option<ConfigGenesis>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub assets: Option<ConfigAssets>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub fee: Option<ConfigFee>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub consensus: Option<ConfigConsensus>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub admin: Option<ConfigAdmin>,
     // FIXME toml fails with empty Vec alexcrichton/toml-rs#384
     #[serde(skip_serializing_if = "Vec::is_empty", default = "Vec::default")]
     pub nodes: Vec<Node>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct Node {
     pub address: String,
     #[serde(with = "hex")]
     pub public_key: exchange::PublicKey,
+    /// Where the node serves its RPC, used to catch up with the network
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rpc: Option<String>,
+    /// Key signing the node's replies, needed to catch up with the node
+    #[serde(skip_serializing_if = "Option::is_none", default, with = "hex_option")]
+    pub sign_key: Option<sign::PublicKey>,
+}
+
+/// Same as `#[serde(with = "hex")]`, for an optional key
+mod hex_option {
+    use drop::crypto::sign;
+    use hex::FromHex;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        key: &Option<sign::PublicKey>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match key {
+            Some(key) => hex::serde::serialize(key, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<sign::PublicKey>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|key| sign::PublicKey::from_hex(key).map_err(de::Error::custom))
+            .transpose()
+    }
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -48,6 +275,9 @@ pub enum Error {
     Read { source: io::Error },
     #[snafu(display("decode: {}", source))]
     Decode { source: toml::de::Error },
+
+    #[snafu(display("invalid consensus: {}", reason))]
+    InvalidConsensus { reason: String },
 }
 
 pub fn from_reader(mut reader: impl io::Read) -> Result<Config, Error> {
@@ -79,3 +309,98 @@ impl Nodes {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_consensus_tolerates_a_third_of_the_network() {
+        let parameters = ConfigConsensus::default()
+            .resolve(3)
+            .expect("to resolve consensus");
+
+        assert_eq!(
+            ConsensusParameters {
+                fault_tolerance: 1,
+                sampling: Sampling::All,
+                sieve_sample_size: 3,
+                echo_threshold: 2,
+                murmur_gossip_size: 3,
+                contagion_sample_size: 3,
+                ready_threshold: 2,
+            },
+            parameters
+        );
+    }
+
+    #[test]
+    fn single_node_consensus_is_valid() {
+        ConfigConsensus::default()
+            .resolve(0)
+            .expect("to resolve consensus");
+    }
+
+    #[test]
+    fn threshold_above_sample_is_invalid() {
+        let consensus = ConfigConsensus {
+            sieve_sample_size: Some(2),
+            echo_threshold: Some(3),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            consensus.resolve(3),
+            Err(Error::InvalidConsensus { .. })
+        ));
+    }
+
+    #[test]
+    fn fault_tolerance_of_a_third_is_invalid() {
+        let consensus = ConfigConsensus {
+            fault_tolerance: Some(1),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            consensus.resolve(2),
+            Err(Error::InvalidConsensus { .. })
+        ));
+        assert!(matches!(
+            consensus.resolve(0),
+            Err(Error::InvalidConsensus { .. })
+        ));
+    }
+
+    #[test]
+    fn threshold_below_quorum_is_invalid() {
+        for consensus in [
+            ConfigConsensus {
+                echo_threshold: Some(1),
+                ..Default::default()
+            },
+            ConfigConsensus {
+                ready_threshold: Some(1),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                consensus.resolve(3),
+                Err(Error::InvalidConsensus { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn sample_above_network_is_invalid() {
+        let consensus = ConfigConsensus {
+            contagion_sample_size: Some(4),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            consensus.resolve(3),
+            Err(Error::InvalidConsensus { .. })
+        ));
+    }
+}
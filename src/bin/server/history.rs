@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write as _},
+    path::Path,
+};
+
+use at2_node::{FullTransaction, HistoryDirection};
+use drop::crypto::sign;
+use snafu::ResultExt;
+use tokio::sync::{mpsc, oneshot};
+
+/// Maximum number of transactions returned at once
+pub const HISTORY_MAX_PAGE_SIZE: usize = 100;
+
+#[derive(snafu::Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("open: {}", source))]
+    Open { source: io::Error },
+    #[snafu(display("read: {}", source))]
+    Read { source: io::Error },
+    #[snafu(display("decode: {}", source))]
+    Decode { source: bincode::Error },
+    #[snafu(display("encode: {}", source))]
+    Encode { source: bincode::Error },
+    #[snafu(display("write: {}", source))]
+    Write { source: io::Error },
+
+    #[snafu(display("gone on send"))]
+    GoneOnSend,
+    #[snafu(display("gone on recv"))]
+    GoneOnRecv,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Transactions of an account
+#[derive(Debug, Default)]
+pub struct Page {
+    pub transactions: Vec<FullTransaction>,
+    /// Where to continue to get the following transactions
+    pub next_cursor: usize,
+}
+
+#[derive(Debug)]
+enum Commands {
+    Record {
+        tx: Box<FullTransaction>,
+        resp: oneshot::Sender<Result<()>>,
+    },
+    Get {
+        user: Box<sign::PublicKey>,
+        direction: HistoryDirection,
+        cursor: usize,
+        page_size: usize,
+        resp: oneshot::Sender<Page>,
+    },
+}
+
+#[derive(Clone)]
+pub struct History {
+    agent: mpsc::Sender<Commands>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Index in the committed transactions
+    index: usize,
+    sent: bool,
+    received: bool,
+}
+
+/// Tokio agent owning the history of every account.
+/// The only way to interacte with it is to use [`History`].
+struct HistoryHandler {
+    /// Every committed transaction, oldest first
+    transactions: Vec<FullTransaction>,
+    /// Committed transactions of each account, oldest first
+    accounts: HashMap<sign::PublicKey, Vec<Entry>>,
+    /// Append-only log of the committed transactions, if persisted
+    file: Option<fs::File>,
+}
+
+impl History {
+    /// Create an history only kept in memory
+    pub fn new() -> Self {
+        Self {
+            agent: HistoryHandler::new(Vec::new(), None).spawn(),
+        }
+    }
+
+    /// Open or create the history stored at the given path
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut transactions = Vec::new();
+        let mut valid_len = 0;
+
+        match fs::read(path) {
+            Ok(content) => {
+                let mut reader = io::Cursor::new(content);
+                while reader.position() < reader.get_ref().len() as u64 {
+                    match bincode::deserialize_from(&mut reader) {
+                        Ok(tx) => {
+                            transactions.push(tx);
+                            valid_len = reader.position();
+                        }
+                        Err(err) => match *err {
+                            // partially written record, crashed while appending
+                            bincode::ErrorKind::Io(ref err)
+                                if err.kind() == io::ErrorKind::UnexpectedEof =>
+                            {
+                                break
+                            }
+                            _ => return Err(err).context(Decode),
+                        },
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(Read),
+        }
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(Open)?;
+        file.set_len(valid_len).context(Write)?;
+
+        Ok(Self {
+            agent: HistoryHandler::new(transactions, Some(file)).spawn(),
+        })
+    }
+
+    /// Add a committed transaction to the history of its sender and recipient
+    pub async fn record(&self, tx: FullTransaction) -> Result<()> {
+        let (resp, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Record {
+                tx: Box::new(tx),
+                resp,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)?
+    }
+
+    /// Return up to `page_size` transactions of the user in the given direction, starting at
+    /// `cursor`
+    ///
+    /// The page size is capped to [`HISTORY_MAX_PAGE_SIZE`], a zero size meaning the maximum.
+    pub async fn get(
+        &self,
+        user: Box<sign::PublicKey>,
+        direction: HistoryDirection,
+        cursor: usize,
+        page_size: usize,
+    ) -> Result<Page> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Get {
+                user,
+                direction,
+                cursor,
+                page_size,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+}
+
+impl HistoryHandler {
+    fn new(transactions: Vec<FullTransaction>, file: Option<fs::File>) -> Self {
+        let mut handler = Self {
+            transactions: Vec::with_capacity(transactions.len()),
+            accounts: HashMap::new(),
+            file,
+        };
+        transactions.into_iter().for_each(|tx| handler.index(tx));
+
+        handler
+    }
+
+    fn spawn(mut self) -> mpsc::Sender<Commands> {
+        let (tx, mut rx) = mpsc::channel(32);
+
+        tokio::spawn(async move {
+            while let Some(cmd) = rx.recv().await {
+                match cmd {
+                    Commands::Record { tx, resp } => {
+                        let _ = resp.send(self.record(*tx));
+                    }
+                    Commands::Get {
+                        user,
+                        direction,
+                        cursor,
+                        page_size,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.get(&user, direction, cursor, page_size));
+                    }
+                }
+            }
+        });
+
+        tx
+    }
+
+    fn record(&mut self, tx: FullTransaction) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            let record = bincode::serialize(&tx).context(Encode)?;
+            file.write_all(&record).context(Write)?;
+            file.sync_data().context(Write)?;
+        }
+
+        self.index(tx);
+
+        Ok(())
+    }
+
+    /// Add the transaction to the history of its sender and recipient
+    fn index(&mut self, tx: FullTransaction) {
+        let (sender, recipient) = (tx.sender, tx.recipient);
+        let index = self.transactions.len();
+        self.transactions.push(tx);
+
+        let entry = |sent, received| Entry {
+            index,
+            sent,
+            received,
+        };
+
+        if sender == recipient {
+            self.accounts
+                .entry(sender)
+                .or_default()
+                .push(entry(true, true));
+        } else {
+            self.accounts
+                .entry(sender)
+                .or_default()
+                .push(entry(true, false));
+            self.accounts
+                .entry(recipient)
+                .or_default()
+                .push(entry(false, true));
+        }
+    }
+
+    fn get(
+        &self,
+        user: &sign::PublicKey,
+        direction: HistoryDirection,
+        cursor: usize,
+        page_size: usize,
+    ) -> Page {
+        let page_size = match page_size {
+            0 => HISTORY_MAX_PAGE_SIZE,
+            size => size.min(HISTORY_MAX_PAGE_SIZE),
+        };
+
+        let entries = match self.accounts.get(user) {
+            None => return Page::default(),
+            Some(entries) => entries,
+        };
+
+        let mut page = Page {
+            transactions: Vec::with_capacity(page_size),
+            next_cursor: entries.len().max(cursor),
+        };
+
+        for (index, entry) in entries.iter().enumerate().skip(cursor) {
+            if page.transactions.len() == page_size {
+                page.next_cursor = index;
+                break;
+            }
+
+            let matching = match direction {
+                HistoryDirection::Sent => entry.sent,
+                HistoryDirection::Received => entry.received,
+                HistoryDirection::Both => true,
+            };
+            if matching {
+                page.transactions
+                    .push(self.transactions[entry.index].clone());
+            }
+        }
+
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use at2_node::{TransactionState, NATIVE_ASSET};
+
+    use super::*;
+
+    fn committed(
+        sender: sign::PublicKey,
+        sender_sequence: sieve::Sequence,
+        recipient: sign::PublicKey,
+    ) -> FullTransaction {
+        FullTransaction {
+            timestamp: chrono::Utc::now(),
+            sender,
+            sender_sequence,
+            recipient,
+            asset: NATIVE_ASSET.to_owned(),
+            amount: sender_sequence.into(),
+            state: TransactionState::Success,
+            memo: None,
+        }
+    }
+
+    fn keys(page: &Page) -> Vec<(sign::PublicKey, sieve::Sequence)> {
+        page.transactions
+            .iter()
+            .map(|tx| (tx.sender, tx.sender_sequence))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn history_is_paginated_by_direction() {
+        let history = History::new();
+
+        let user = sign::KeyPair::random().public();
+        let other = sign::KeyPair::random().public();
+
+        for seq in 1..=3 {
+            history
+                .record(committed(user, seq, other))
+                .await
+                .expect("to record sent transaction");
+            history
+                .record(committed(other, seq, user))
+                .await
+                .expect("to record received transaction");
+        }
+
+        let first = history
+            .get(Box::new(user), HistoryDirection::Sent, 0, 2)
+            .await
+            .expect("to get first page");
+        assert_eq!(vec![(user, 1), (user, 2)], keys(&first));
+
+        let second = history
+            .get(Box::new(user), HistoryDirection::Sent, first.next_cursor, 2)
+            .await
+            .expect("to get second page");
+        assert_eq!(vec![(user, 3)], keys(&second));
+
+        let last = history
+            .get(
+                Box::new(user),
+                HistoryDirection::Sent,
+                second.next_cursor,
+                2,
+            )
+            .await
+            .expect("to get last page");
+        assert!(last.transactions.is_empty());
+
+        let received = history
+            .get(Box::new(user), HistoryDirection::Received, 0, 0)
+            .await
+            .expect("to get received");
+        assert_eq!(vec![(other, 1), (other, 2), (other, 3)], keys(&received));
+
+        let both = history
+            .get(Box::new(user), HistoryDirection::Both, 0, 0)
+            .await
+            .expect("to get both");
+        assert_eq!(6, both.transactions.len());
+    }
+
+    #[tokio::test]
+    async fn reopened_history_keeps_recorded_transactions() {
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("history");
+
+        let user = sign::KeyPair::random().public();
+        let other = sign::KeyPair::random().public();
+        let txs = (1..=3)
+            .map(|seq| committed(user, seq, other))
+            .collect::<Vec<_>>();
+
+        {
+            let history = History::open(&path).expect("to open history");
+            for tx in txs.iter() {
+                history
+                    .record(tx.clone())
+                    .await
+                    .expect("to record transaction");
+            }
+        }
+
+        let history = History::open(&path).expect("to reopen history");
+        let received = history
+            .get(Box::new(other), HistoryDirection::Received, 0, 0)
+            .await
+            .expect("to get received");
+
+        assert_eq!(txs, received.transactions);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use at2_node::Operation;
+use snafu::{ResultExt, Snafu};
+
+pub type Payload = sieve::Payload<Operation>;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("open: {}", source))]
+    Open { source: io::Error },
+    #[snafu(display("read: {}", source))]
+    Read { source: io::Error },
+    #[snafu(display("decode: {}", source))]
+    Decode { source: bincode::Error },
+    #[snafu(display("encode: {}", source))]
+    Encode { source: bincode::Error },
+    #[snafu(display("write: {}", source))]
+    Write { source: io::Error },
+}
+
+/// Append-only log of the delivered payloads, written before processing them
+///
+/// Once applied to the stored accounts, the payloads are not needed anymore, so it is compacted
+/// from time to time to only keep the pending ones.
+pub struct Journal {
+    file: fs::File,
+    path: PathBuf,
+    /// Number of payloads it contains
+    records: usize,
+}
+
+impl Journal {
+    /// Open or create the journal at the given path
+    ///
+    /// Return it with the payloads it already contains, in delivery order.
+    pub fn open(path: &Path) -> Result<(Self, Vec<Payload>), Error> {
+        let mut payloads = Vec::new();
+        let mut valid_len = 0;
+
+        match fs::read(path) {
+            Ok(content) => {
+                let mut reader = io::Cursor::new(content);
+                while reader.position() < reader.get_ref().len() as u64 {
+                    match bincode::deserialize_from(&mut reader) {
+                        Ok(payload) => {
+                            payloads.push(payload);
+                            valid_len = reader.position();
+                        }
+                        Err(err) => match *err {
+                            // partially written record, crashed while appending
+                            bincode::ErrorKind::Io(ref err)
+                                if err.kind() == io::ErrorKind::UnexpectedEof =>
+                            {
+                                break
+                            }
+                            _ => return Err(err).context(Decode),
+                        },
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(Read),
+        }
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(Open)?;
+        file.set_len(valid_len).context(Write)?;
+
+        Ok((
+            Self {
+                file,
+                path: path.to_owned(),
+                records: payloads.len(),
+            },
+            payloads,
+        ))
+    }
+
+    /// Durably append the given payloads
+    pub fn append<'a>(&mut self, payloads: impl Iterator<Item = &'a Payload>) -> Result<(), Error> {
+        let (records, count) = encode(payloads)?;
+
+        self.file.write_all(&records).context(Write)?;
+        self.file.sync_data().context(Write)?;
+        self.records += count;
+
+        Ok(())
+    }
+
+    /// Replace the content by the given payloads at once, the others being already applied
+    ///
+    /// It is only to be called once the applied payloads are durably stored with the accounts.
+    pub fn compact<'a>(&mut self, pending: impl Iterator<Item = &'a Payload>) -> Result<(), Error> {
+        let (records, count) = encode(pending)?;
+
+        let staged_path = self.path.with_extension("staged");
+        let mut staged = fs::File::create(&staged_path).context(Open)?;
+        staged.write_all(&records).context(Write)?;
+        staged.sync_all().context(Write)?;
+        fs::rename(&staged_path, &self.path).context(Write)?;
+
+        self.file = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .context(Open)?;
+        self.records = count;
+
+        Ok(())
+    }
+
+    /// Number of payloads it contains, applied or not
+    pub fn records(&self) -> usize {
+        self.records
+    }
+}
+
+fn encode<'a>(payloads: impl Iterator<Item = &'a Payload>) -> Result<(Vec<u8>, usize), Error> {
+    let mut records = Vec::new();
+    let mut count = 0;
+    for payload in payloads {
+        bincode::serialize_into(&mut records, payload).context(Encode)?;
+        count += 1;
+    }
+
+    Ok((records, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use at2_node::{ThinTransaction, NATIVE_ASSET};
+    use drop::crypto::sign;
+
+    use super::*;
+
+    fn payloads(count: sieve::Sequence) -> Vec<Payload> {
+        let sender = sign::KeyPair::random();
+
+        (1..=count)
+            .map(|sequence| {
+                let operation = Operation::Transfer(ThinTransaction {
+                    recipient: sign::KeyPair::random().public(),
+                    asset: NATIVE_ASSET.to_owned(),
+                    amount: sequence.into(),
+                    memo: None,
+                });
+                let signature = sender.sign(&operation).expect("sign failed");
+
+                Payload::new(sender.public(), sequence, operation, signature)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reopen_returns_appended_payloads() {
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("journal");
+        let payloads = payloads(3);
+
+        {
+            let (mut journal, replayed) = Journal::open(&path).expect("to open journal");
+            assert!(replayed.is_empty());
+
+            journal
+                .append(payloads.iter().take(1))
+                .expect("to append payloads");
+            journal
+                .append(payloads.iter().skip(1))
+                .expect("to append payloads");
+        }
+
+        let (_, replayed) = Journal::open(&path).expect("to reopen journal");
+
+        assert_eq!(payloads.len(), replayed.len());
+        payloads
+            .iter()
+            .zip(replayed.iter())
+            .for_each(|(appended, replayed)| {
+                assert_eq!(appended.sender(), replayed.sender());
+                assert_eq!(appended.sequence(), replayed.sequence());
+                assert_eq!(appended.payload(), replayed.payload());
+            });
+    }
+    #[test]
+    fn compacted_journal_only_keeps_pending_payloads() {
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("journal");
+        let payloads = payloads(4);
+
+        {
+            let (mut journal, _) = Journal::open(&path).expect("to open journal");
+            journal
+                .append(payloads.iter().take(3))
+                .expect("to append payloads");
+
+            journal
+                .compact(payloads.iter().skip(2).take(1))
+                .expect("to compact journal");
+            assert_eq!(1, journal.records());
+
+            journal
+                .append(payloads.iter().skip(3))
+                .expect("to append payloads");
+            assert_eq!(2, journal.records());
+        }
+
+        let (journal, replayed) = Journal::open(&path).expect("to reopen journal");
+
+        assert_eq!(2, journal.records());
+        assert_eq!(
+            vec![3, 4],
+            replayed
+                .iter()
+                .map(|payload| payload.sequence())
+                .collect::<Vec<_>>()
+        );
+    }
+}
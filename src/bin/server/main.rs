@@ -1,32 +1,90 @@
-use std::{io, process};
+use std::{
+    fs,
+    io::{self, Write as _},
+    path::PathBuf,
+    process,
+};
 
 use at2_node::proto;
 use drop::crypto::{key::exchange, sign};
-use snafu::{OptionExt, ResultExt, Snafu};
+use hex::FromHex;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use structopt::StructOpt;
 use tokio::net;
-use tonic::transport::Server;
+use tonic::transport::{Endpoint, Server};
 use tracing::{subscriber, Level};
 use tracing_fmt::FmtSubscriber;
 
 mod accounts;
+use accounts::{storage::Storage as _, Accounts};
 mod config;
+mod history;
+use history::History;
+mod journal;
+use journal::Journal;
+mod membership;
+use membership::Membership;
+mod network;
+mod peers;
+mod pending;
 mod recent_transactions;
 mod rpc;
+mod sync;
 
+fn hex_decode<T: FromHex>(src: &str) -> Result<T, T::Error> {
+    T::from_hex(src)
+}
+
+// only parsed once, at startup
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 enum Commands {
     Config(CommandsConfig),
     Run,
+    Snapshot(CommandsSnapshot),
 }
 
+// only parsed once, at startup
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 enum CommandsConfig {
     New {
         node_address: String,
         rpc_address: String,
+        /// Directory where to persist the state
+        #[structopt(long)]
+        storage: Option<PathBuf>,
+        /// Public key of the account owning the asset at genesis
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        faucet: Option<sign::PublicKey>,
+        #[structopt(long, default_value = "1000000000")]
+        faucet_balance: u64,
+        /// Public key of the account allowed to issue new assets
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        issuer: Option<sign::PublicKey>,
+        /// Amount of asset paid for every transfer
+        #[structopt(long, requires = "treasury")]
+        fee: Option<u64>,
+        /// Public key of the account receiving the fees
+        #[structopt(long, parse(try_from_str = hex_decode), requires = "fee")]
+        treasury: Option<sign::PublicKey>,
+        /// Public key of the account allowed to add and remove nodes
+        #[structopt(long, parse(try_from_str = hex_decode))]
+        admin: Option<sign::PublicKey>,
     },
     GetNode,
+    /// Show the key signing the replies, for the clients to check them
+    GetPublicKey,
+}
+
+#[derive(Debug, StructOpt)]
+enum CommandsSnapshot {
+    /// Write the accounts of the running node
+    Export,
+    /// Store the accounts of a snapshot, while the node is stopped, then show its state root
+    ///
+    /// Only done on a storage without any account nor history, the journal being reset.
+    Import { path: PathBuf },
 }
 
 #[derive(Debug, Snafu)]
@@ -35,10 +93,56 @@ enum RunError {
     Logging {
         source: tracing::dispatcher::SetGlobalDefaultError,
     },
+    #[snafu(display("create storage directory: {}", source))]
+    StorageDirectory { source: io::Error },
+    #[snafu(display("open accounts: {}", source))]
+    AccountsStorage { source: accounts::storage::Error },
+    #[snafu(display("create accounts: {}", source))]
+    AccountsNew { source: accounts::Error },
+    #[snafu(display("open journal: {}", source))]
+    JournalStorage { source: journal::Error },
+    #[snafu(display("open history: {}", source))]
+    HistoryStorage { source: history::Error },
+    #[snafu(display("open membership: {}", source))]
+    MembershipStorage { source: membership::Error },
     #[snafu(display("service: {}", source))]
     Service { source: rpc::Error },
     #[snafu(display("rpc: {}", source))]
     Rpc { source: tonic::transport::Error },
+    #[snafu(display("process payloads: {}", source))]
+    Processing { source: rpc::Error },
+    #[snafu(display("process payloads: {}", source))]
+    ProcessingJoin { source: tokio::task::JoinError },
+}
+
+#[derive(Debug, Snafu)]
+enum SnapshotError {
+    #[snafu(display("read config: {}", source))]
+    ReadConfig { source: config::Error },
+    #[snafu(display("invalid rpc address: {}", source))]
+    InvalidRpcAddress { source: http::uri::InvalidUri },
+    #[snafu(display("connect: {}", source))]
+    Connect { source: tonic::transport::Error },
+    #[snafu(display("get snapshot: {}", source))]
+    GetSnapshot { source: tonic::Status },
+    #[snafu(display("write snapshot: {}", source))]
+    WriteSnapshot { source: io::Error },
+    #[snafu(display("read snapshot: {}", source))]
+    ReadSnapshot { source: io::Error },
+    #[snafu(display("decode snapshot: {}", source))]
+    DecodeSnapshot { source: accounts::snapshot::Error },
+    #[snafu(display("no storage configured"))]
+    NoStorage,
+    #[snafu(display("store accounts: {}", source))]
+    StoreAccounts { source: accounts::storage::Error },
+    #[snafu(display("storage not empty, only importing into a new one"))]
+    StorageNotEmpty,
+    #[snafu(display("read history: {}", source))]
+    ReadHistory { source: io::Error },
+    #[snafu(display("reset journal: {}", source))]
+    ResetJournal { source: journal::Error },
+    #[snafu(display("create storage directory: {}", source))]
+    CreateStorageDirectory { source: io::Error },
 }
 
 #[derive(Debug, Snafu)]
@@ -51,6 +155,8 @@ enum Error {
     NoHost,
     #[snafu(display("run server: {}", source))]
     Run { source: RunError },
+    #[snafu(display("snapshot: {}", source))]
+    Snapshot { source: SnapshotError },
 }
 
 fn config(cmd: CommandsConfig) -> Result<(), Error> {
@@ -58,6 +164,13 @@ fn config(cmd: CommandsConfig) -> Result<(), Error> {
         CommandsConfig::New {
             node_address,
             rpc_address,
+            storage,
+            faucet,
+            faucet_balance,
+            issuer,
+            fee,
+            treasury,
+            admin,
         } => config::Config {
             addresses: config::ConfigAddresses {
                 node: node_address,
@@ -67,6 +180,20 @@ fn config(cmd: CommandsConfig) -> Result<(), Error> {
                 sign: sign::KeyPair::random().private(),
                 network: exchange::KeyPair::random().secret().to_owned(),
             },
+            storage: storage.map(|directory| config::ConfigStorage { directory }),
+            genesis: faucet.map(|public_key| config::ConfigGenesis {
+                faucet: config::GenesisAccount {
+                    public_key,
+                    balance: faucet_balance,
+                },
+                accounts: vec![],
+            }),
+            assets: issuer.map(|issuer| config::ConfigAssets { issuer }),
+            fee: fee
+                .zip(treasury)
+                .map(|(amount, treasury)| config::ConfigFee { treasury, amount }),
+            consensus: None,
+            admin: admin.map(|public_key| config::ConfigAdmin { public_key }),
             nodes: vec![],
         }
         .to_writer(io::stdout())
@@ -80,11 +207,77 @@ fn config(cmd: CommandsConfig) -> Result<(), Error> {
                     public_key: exchange::KeyPair::new(config.keys.network)
                         .public()
                         .to_owned(),
+                    rpc: Some(config.addresses.rpc),
+                    sign_key: Some(sign::KeyPair::from(config.keys.sign).public()),
                 }],
             }
             .to_writer(io::stdout())
             .context(Config)
         }
+        CommandsConfig::GetPublicKey => {
+            let config = config::from_reader(io::stdin()).context(Config)?;
+
+            println!("{}", sign::KeyPair::from(config.keys.sign).public());
+
+            Ok(())
+        }
+    }
+}
+
+async fn snapshot(cmd: CommandsSnapshot) -> Result<(), SnapshotError> {
+    let config = config::from_reader(io::stdin()).context(ReadConfig)?;
+
+    match cmd {
+        CommandsSnapshot::Export => {
+            let channel = Endpoint::from_shared(format!("http://{}", config.addresses.rpc))
+                .context(InvalidRpcAddress)?
+                .connect()
+                .await
+                .context(Connect)?;
+
+            let snapshot = proto::at2_client::At2Client::new(channel)
+                .get_snapshot(tonic::Request::new(proto::GetSnapshotRequest {}))
+                .await
+                .context(GetSnapshot)?
+                .into_inner()
+                .snapshot;
+
+            io::stdout().write_all(&snapshot).context(WriteSnapshot)
+        }
+        CommandsSnapshot::Import { path } => {
+            let storage = config.storage.context(NoStorage)?;
+
+            let snapshot =
+                accounts::snapshot::Snapshot::decode(&fs::read(path).context(ReadSnapshot)?)
+                    .context(DecodeSnapshot)?;
+            let state_root = snapshot.state_root().context(DecodeSnapshot)?;
+
+            fs::create_dir_all(&storage.directory).context(CreateStorageDirectory)?;
+            let mut accounts = accounts::storage::File::open(&storage.directory.join("accounts"))
+                .context(StoreAccounts)?;
+            let history_len = match fs::metadata(storage.directory.join("history")) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+                Err(err) => return Err(err).context(ReadHistory),
+            };
+            ensure!(
+                accounts.all().is_empty() && history_len == 0,
+                StorageNotEmpty
+            );
+
+            // delivered before the snapshot, not to be applied on top of it
+            let (mut journal, _) =
+                Journal::open(&storage.directory.join("journal")).context(ResetJournal)?;
+            journal.compact(std::iter::empty()).context(ResetJournal)?;
+
+            accounts
+                .set_all(snapshot.into_accounts())
+                .context(StoreAccounts)?;
+
+            println!("{}", hex::encode(state_root));
+
+            Ok(())
+        }
     }
 }
 
@@ -98,10 +291,73 @@ async fn run() -> Result<(), Error> {
         .context(Logging)
         .context(Run)?;
 
-    let service = rpc::Service::new(
+    let genesis = config
+        .genesis
+        .iter()
+        .flat_map(|genesis| genesis.accounts())
+        .map(|account| (account.public_key, account.balance))
+        .collect::<Vec<_>>();
+    let fee = config.fee.map(|fee| accounts::Fee {
+        treasury: fee.treasury,
+        amount: fee.amount,
+    });
+
+    let own = exchange::KeyPair::new(config.keys.network.to_owned())
+        .public()
+        .to_owned();
+
+    let state = match config.storage {
+        None => rpc::State {
+            accounts: Accounts::new(accounts::storage::Memory::default(), genesis, fee)
+                .context(AccountsNew)
+                .context(Run)?,
+            history: History::new(),
+            journal: None,
+            membership: Membership::new(config.nodes, own),
+        },
+        Some(storage) => {
+            fs::create_dir_all(&storage.directory)
+                .context(StorageDirectory)
+                .context(Run)?;
+
+            let accounts = Accounts::new(
+                accounts::storage::File::open(&storage.directory.join("accounts"))
+                    .context(AccountsStorage)
+                    .context(Run)?,
+                genesis,
+                fee,
+            )
+            .context(AccountsNew)
+            .context(Run)?;
+            let history = History::open(&storage.directory.join("history"))
+                .context(HistoryStorage)
+                .context(Run)?;
+            let journal = Journal::open(&storage.directory.join("journal"))
+                .context(JournalStorage)
+                .context(Run)?;
+            let membership =
+                Membership::open(&storage.directory.join("membership"), config.nodes, own)
+                    .context(MembershipStorage)
+                    .context(Run)?;
+
+            rpc::State {
+                accounts,
+                history,
+                journal: Some(journal),
+                membership,
+            }
+        }
+    };
+
+    let (service, processing) = rpc::Service::new(
         config.addresses.node,
-        exchange::KeyPair::new(config.keys.network),
-        config.nodes,
+        config.keys,
+        config.consensus.unwrap_or_default(),
+        rpc::Authorities {
+            issuer: config.assets.map(|assets| assets.issuer),
+            admin: config.admin.map(|admin| admin.public_key),
+        },
+        state,
     )
     .await
     .context(Service)
@@ -109,7 +365,7 @@ async fn run() -> Result<(), Error> {
 
     let web_config = tonic_web::config().allow_all_origins();
 
-    Server::builder()
+    let serving = Server::builder()
         .accept_http1(true)
         .add_service(web_config.enable(proto::at2_server::At2Server::new(service)))
         .serve(
@@ -118,12 +374,17 @@ async fn run() -> Result<(), Error> {
                 .context(UnknownHost)?
                 .next()
                 .context(NoHost)?,
-        )
-        .await
-        .context(Rpc)
-        .context(Run)?;
+        );
 
-    Ok(())
+    // stop serving once unable to process, as the state would be outdated
+    tokio::select! {
+        served = serving => served.context(Rpc).context(Run),
+        processed = processing => processed
+            .context(ProcessingJoin)
+            .context(Run)?
+            .context(Processing)
+            .context(Run),
+    }
 }
 
 #[tokio::main(flavor = "multi_thread")]
@@ -131,6 +392,7 @@ async fn main() {
     let ret = match Commands::from_args() {
         Commands::Config(cmd) => config(cmd),
         Commands::Run => run().await,
+        Commands::Snapshot(cmd) => snapshot(cmd).await.context(Snapshot),
     };
 
     if let Err(err) = ret {
@@ -0,0 +1,241 @@
+use std::{
+    fs,
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use at2_node::MembershipChange;
+use drop::crypto::{key::exchange, sign};
+use snafu::{ResultExt, Snafu};
+use tracing::info;
+
+use super::config;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("open {:?}: {}", path, source))]
+    Open { path: PathBuf, source: io::Error },
+    #[snafu(display("read: {}", source))]
+    Read { source: io::Error },
+    #[snafu(display("decode: {}", source))]
+    Decode { source: bincode::Error },
+    #[snafu(display("encode: {}", source))]
+    Encode { source: bincode::Error },
+    #[snafu(display("write: {}", source))]
+    Write { source: io::Error },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Record {
+    nodes: Vec<StoredNode>,
+    /// Sequence of the admin's last applied change
+    last_sequence: Option<sieve::Sequence>,
+}
+
+/// Same as [`config::Node`], without skipping the fields only optional in the config
+#[derive(serde::Deserialize, serde::Serialize)]
+struct StoredNode {
+    address: String,
+    public_key: exchange::PublicKey,
+    rpc: Option<String>,
+    sign_key: Option<sign::PublicKey>,
+}
+
+/// Other nodes of the network, as agreed on by the membership changes
+///
+/// If stored, it replaces the configured nodes on restart, the changes already applied not
+/// being taken from the journal again.
+pub struct Membership {
+    nodes: Vec<config::Node>,
+    last_sequence: Option<sieve::Sequence>,
+    /// Public key of this node, never one of the others
+    own: exchange::PublicKey,
+    path: Option<PathBuf>,
+}
+
+impl Membership {
+    /// Start from the given nodes, the changes are lost on restart
+    pub fn new(nodes: Vec<config::Node>, own: exchange::PublicKey) -> Self {
+        Self {
+            nodes: nodes
+                .into_iter()
+                .filter(|node| node.public_key != own)
+                .collect(),
+            last_sequence: None,
+            own,
+            path: None,
+        }
+    }
+
+    /// Open the membership stored at the given path, or start from the given nodes if none is
+    pub fn open(path: &Path, nodes: Vec<config::Node>, own: exchange::PublicKey) -> Result<Self> {
+        let mut membership = Self::new(nodes, own);
+
+        match fs::read(path) {
+            Ok(content) => {
+                let record: Record = bincode::deserialize(&content).context(Decode)?;
+
+                membership.nodes = record
+                    .nodes
+                    .into_iter()
+                    .map(|node| config::Node {
+                        address: node.address,
+                        public_key: node.public_key,
+                        rpc: node.rpc,
+                        sign_key: node.sign_key,
+                    })
+                    .collect();
+                membership.last_sequence = record.last_sequence;
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).context(Read),
+        }
+        membership.path = Some(path.to_owned());
+
+        Ok(membership)
+    }
+
+    pub fn nodes(&self) -> &[config::Node] {
+        &self.nodes
+    }
+
+    /// Apply and store the change, returning if the nodes changed
+    ///
+    /// A change not following the last applied one is ignored, as already applied.
+    pub fn apply(&mut self, sequence: sieve::Sequence, change: MembershipChange) -> Result<bool> {
+        if matches!(self.last_sequence, Some(last) if sequence <= last) {
+            return Ok(false);
+        }
+        self.last_sequence = Some(sequence);
+
+        let changed = match change {
+            MembershipChange::Add { public_key, .. } if public_key == self.own => false,
+            MembershipChange::Add {
+                public_key,
+                address,
+                rpc,
+                sign_key,
+            } => {
+                let known = self.nodes.iter().any(|node| node.public_key == public_key);
+                if !known {
+                    info!(address = %address, "node joined");
+                    self.nodes.push(config::Node {
+                        address,
+                        public_key,
+                        rpc: Some(rpc),
+                        sign_key: Some(sign_key),
+                    });
+                }
+                !known
+            }
+            MembershipChange::Remove { public_key } => {
+                let count = self.nodes.len();
+                self.nodes.retain(|node| node.public_key != public_key);
+                self.nodes.len() != count
+            }
+        };
+
+        self.store()?;
+
+        Ok(changed)
+    }
+
+    /// Replace the stored record at once, to not leave a partial one when crashing
+    fn store(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let staged_path = path.with_extension("staged");
+        let mut staged = fs::File::create(&staged_path).context(Open {
+            path: staged_path.clone(),
+        })?;
+        let record = Record {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| StoredNode {
+                    address: node.address.clone(),
+                    public_key: node.public_key,
+                    rpc: node.rpc.clone(),
+                    sign_key: node.sign_key,
+                })
+                .collect(),
+            last_sequence: self.last_sequence,
+        };
+        staged
+            .write_all(&bincode::serialize(&record).context(Encode)?)
+            .context(Write)?;
+        staged.sync_all().context(Write)?;
+
+        fs::rename(&staged_path, path).context(Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(address: &str) -> config::Node {
+        config::Node {
+            address: address.to_owned(),
+            public_key: exchange::KeyPair::random().public().to_owned(),
+            rpc: Some(address.to_owned()),
+            sign_key: Some(sign::KeyPair::random().public()),
+        }
+    }
+
+    fn add(node: &config::Node) -> MembershipChange {
+        MembershipChange::Add {
+            public_key: node.public_key,
+            address: node.address.clone(),
+            rpc: node.rpc.clone().expect("node with rpc"),
+            sign_key: node.sign_key.expect("node with sign key"),
+        }
+    }
+
+    #[test]
+    fn reopened_membership_keeps_applied_changes() {
+        let dir = tempfile::tempdir().expect("create temporary directory");
+        let path = dir.path().join("membership");
+        let own = exchange::KeyPair::random().public().to_owned();
+        let (configured, joined) = (node("127.0.0.1:1"), node("127.0.0.1:2"));
+
+        {
+            let mut membership =
+                Membership::open(&path, vec![configured.clone()], own).expect("to open membership");
+            assert!(membership.apply(1, add(&joined)).expect("to add node"));
+            assert!(membership
+                .apply(
+                    2,
+                    MembershipChange::Remove {
+                        public_key: configured.public_key,
+                    },
+                )
+                .expect("to remove node"));
+        }
+
+        let mut reopened =
+            Membership::open(&path, vec![configured.clone()], own).expect("to reopen membership");
+        assert_eq!(1, reopened.nodes().len());
+        assert_eq!(joined.public_key, reopened.nodes()[0].public_key);
+        assert_eq!(joined.rpc, reopened.nodes()[0].rpc);
+        assert_eq!(joined.sign_key, reopened.nodes()[0].sign_key);
+
+        // replayed from the journal
+        assert!(!reopened.apply(1, add(&configured)).expect("to skip change"));
+        assert_eq!(1, reopened.nodes().len());
+    }
+
+    #[test]
+    fn own_node_is_never_added() {
+        let own = node("127.0.0.1:1");
+        let mut membership = Membership::new(vec![], own.public_key);
+
+        assert!(!membership.apply(1, add(&own)).expect("to apply change"));
+        assert!(membership.nodes().is_empty());
+    }
+}
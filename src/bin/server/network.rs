@@ -0,0 +1,262 @@
+use std::{fmt, iter, net::SocketAddr, sync::Arc};
+
+use at2_node::Operation;
+use contagion::{Contagion, ContagionConfig, ContagionMessage};
+use drop::{
+    crypto::key::exchange::Exchanger,
+    net::{
+        Connection, ConnectorExt, Listener, ListenerError, ResolveConnector, TcpConnector,
+        TcpListener,
+    },
+    system::{AllSampler, NetworkSender, PoissonSampler, System, SystemManager},
+};
+use futures::{future, StreamExt};
+use murmur::MurmurConfig;
+use sieve::SieveConfig;
+use snafu::{ResultExt, Snafu};
+use tokio::{
+    net,
+    sync::{mpsc, watch},
+};
+use tracing::{info, warn};
+
+use super::config;
+
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("listen: {}", source))]
+    Listen { source: ListenerError },
+    #[snafu(display("consensus: {}", source))]
+    Consensus { source: config::Error },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Broadcast of the operations to the other nodes
+pub type Handle = contagion::ContagionHandle<
+    Operation,
+    NetworkSender<ContagionMessage<Operation>>,
+    contagion::Fixed,
+>;
+
+/// Gives the links to the running system, dropping them if none is running yet
+#[derive(Clone)]
+pub struct Links(watch::Receiver<Option<mpsc::Sender<Connection>>>);
+
+/// Listener handing to its system the links accepted or established by the node
+struct Dialer {
+    links: mpsc::Receiver<Connection>,
+    exchanger: Exchanger,
+}
+
+/// Broadcast among the agreed nodes, restarted on every membership change
+///
+/// The samples of the broadcast are drawn when it starts, so it has to be restarted for them to
+/// follow the nodes joining and leaving. Every node restarts it when applying the change, which
+/// they all deliver, but not at the same time: the previous broadcast is kept delivering until
+/// the following change, for the payloads sent on it by the nodes yet to apply the change.
+#[derive(Clone)]
+pub struct Network {
+    exchanger: Exchanger,
+    consensus: Arc<config::ConfigConsensus>,
+    systems: Arc<watch::Sender<Option<mpsc::Sender<Connection>>>>,
+    /// Running broadcasts, the latest last
+    handles: Arc<watch::Sender<Vec<Handle>>>,
+    handle: watch::Receiver<Vec<Handle>>,
+}
+
+impl Links {
+    /// Hand the link to the running system
+    pub async fn give(&self, connection: Connection) {
+        let system = self.0.borrow().clone();
+
+        if let Some(system) = system {
+            let _ = system.send(connection).await;
+        }
+    }
+}
+
+/// Links to no system, every one dropped
+impl Default for Links {
+    fn default() -> Self {
+        Self(watch::channel(None).1)
+    }
+}
+
+impl Network {
+    /// Listen for the other nodes, not broadcasting until started
+    pub async fn listen(
+        listener_addr: impl net::ToSocketAddrs + fmt::Display,
+        exchanger: Exchanger,
+        consensus: config::ConfigConsensus,
+    ) -> Result<(Self, Links)> {
+        let mut listener = TcpListener::new(listener_addr, exchanger.clone())
+            .await
+            .context(Listen)?;
+
+        let (systems, links) = watch::channel(None);
+        let links = Links(links);
+
+        // accepted outside of any system, to be given to the one currently running
+        let accepted = links.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok(connection) => accepted.give(connection).await,
+                    Err(err) => warn!("listener error: {}", err),
+                }
+            }
+        });
+
+        let (handles, handle) = watch::channel(Vec::new());
+
+        Ok((
+            Self {
+                exchanger,
+                consensus: Arc::new(consensus),
+                systems: Arc::new(systems),
+                handles: Arc::new(handles),
+                handle,
+            },
+            links,
+        ))
+    }
+
+    /// Latest broadcast, the one to send on, if started
+    pub fn handle(&self) -> Option<Handle> {
+        self.handle.borrow().last().cloned()
+    }
+
+    /// Follow the running broadcasts, to deliver from, changed on every restart
+    pub fn handles(&self) -> watch::Receiver<Vec<Handle>> {
+        self.handle.clone()
+    }
+
+    /// Start broadcasting among the given nodes, waiting to be connected to every one of them
+    pub async fn start(&self, nodes: &[config::Node]) -> Result<()> {
+        self.run(nodes, true).await
+    }
+
+    /// Restart the broadcast among the given nodes, if started
+    ///
+    /// The unreachable nodes are left to the peers to link, only sampled on the next restart.
+    /// The previous broadcast is still delivered from, the one before it being stopped.
+    pub async fn restart(&self, nodes: &[config::Node]) -> Result<()> {
+        if self.handle.borrow().is_empty() {
+            return Ok(());
+        }
+
+        self.run(nodes, false).await
+    }
+
+    async fn run(&self, nodes: &[config::Node], wait_for_all: bool) -> Result<()> {
+        let consensus = self.consensus.resolve(nodes.len()).context(Consensus)?;
+
+        let connector = ResolveConnector::new(TcpConnector::new(self.exchanger.clone()));
+        let candidates = nodes
+            .iter()
+            .map(|node| (node.public_key, node.address.clone()));
+        let mut system = if wait_for_all {
+            System::new_with_connector_zipped(&connector.retry(), candidates).await
+        } else {
+            System::new_with_connector_zipped(&connector, candidates).await
+        };
+
+        let (links, dialed) = mpsc::channel(32);
+        let listener_errors = system
+            .add_listener(Dialer {
+                links: dialed,
+                exchanger: self.exchanger.clone(),
+            })
+            .await;
+        tokio::spawn(async move {
+            listener_errors
+                .for_each(|err| {
+                    warn!("listener error: {}", err);
+                    future::ready(())
+                })
+                .await
+        });
+        let _ = self.systems.send(Some(links));
+
+        let manager = SystemManager::new(system);
+
+        let contagion = Contagion::new(
+            contagion::Fixed::new_local(),
+            ContagionConfig {
+                sieve: SieveConfig {
+                    sieve_sample_size: consensus.sieve_sample_size,
+                    echo_threshold: consensus.echo_threshold,
+                    murmur: MurmurConfig {
+                        murmur_gossip_size: consensus.murmur_gossip_size,
+                        ..Default::default()
+                    },
+                },
+                contagion_sample_size: consensus.contagion_sample_size,
+                ready_threshold: consensus.ready_threshold,
+            },
+        );
+
+        let mut handle = match consensus.sampling {
+            config::Sampling::All => {
+                manager
+                    .run(contagion, AllSampler::default(), num_cpus::get())
+                    .await
+            }
+            config::Sampling::Poisson => {
+                manager
+                    .run(contagion, PoissonSampler::default(), num_cpus::get())
+                    .await
+            }
+        };
+
+        let handle_errors = handle.errors();
+        tokio::spawn(async move {
+            if let Some(stream) = handle_errors {
+                stream
+                    .for_each(|err| {
+                        warn!("handle error: {}", err);
+                        future::ready(())
+                    })
+                    .await
+            }
+        });
+
+        info!(nodes = nodes.len(), "broadcast started");
+        let previous = self.handle.borrow().last().cloned();
+        let _ = self.handles.send(
+            previous
+                .into_iter()
+                .chain(iter::once(handle.processor_handle()))
+                .collect(),
+        );
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Listener for Dialer {
+    type Candidate = SocketAddr;
+
+    async fn establish(&mut self) -> std::result::Result<Connection, ListenerError> {
+        match self.links.recv().await {
+            Some(connection) => Ok(connection),
+            // replaced by the next system, as for a listener never accepting any more link
+            None => future::pending().await,
+        }
+    }
+
+    /// Give the link as is, its keys were already exchanged
+    async fn accept(&mut self) -> std::result::Result<Connection, ListenerError> {
+        self.establish().await
+    }
+
+    async fn local_addr(&self) -> Option<Self::Candidate> {
+        None
+    }
+
+    fn exchanger(&self) -> &Exchanger {
+        &self.exchanger
+    }
+}
@@ -0,0 +1,481 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use at2_node::MembershipChange;
+use drop::{
+    crypto::key::exchange::{self, Exchanger},
+    net::{Connector as _, ResolveConnector, TcpConnector},
+};
+use snafu::ResultExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use super::{
+    config,
+    membership::{self, Membership},
+    network::Links,
+};
+
+/// Delay before replacing the link to a connected peer, to recover the ones silently dropped
+const LINK_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// How long to wait for a peer to accept a connection and exchange keys
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay before the first reconnection attempt, doubled after each failure
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+#[derive(snafu::Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("gone on send"))]
+    GoneOnSend,
+    #[snafu(display("gone on recv"))]
+    GoneOnRecv,
+    #[snafu(display("store membership: {}", source))]
+    StoreMembership { source: membership::Error },
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Connects to the peers, the same way the system does
+pub type Connector = ResolveConnector<TcpConnector>;
+
+/// Health of the link to a node of the network
+#[derive(Debug, Clone)]
+pub struct PeerStatus {
+    pub public_key: exchange::PublicKey,
+    pub address: String,
+    pub connected: bool,
+    /// Last time the peer was reached
+    pub last_seen: Option<chrono::DateTime<chrono::Utc>>,
+    /// Failed attempts since the peer was last reached
+    pub reconnect_attempts: u32,
+}
+
+#[derive(Debug)]
+enum Commands {
+    Apply {
+        sequence: sieve::Sequence,
+        change: MembershipChange,
+        resp: oneshot::Sender<Result<Applied>>,
+    },
+    Members {
+        resp: oneshot::Sender<Vec<config::Node>>,
+    },
+    Seen {
+        peer: usize,
+        resp: oneshot::Sender<bool>,
+    },
+    Lost {
+        peer: usize,
+        resp: oneshot::Sender<bool>,
+    },
+    GetAll {
+        resp: oneshot::Sender<Vec<(usize, PeerStatus)>>,
+    },
+}
+
+/// How the peers changed, for the new ones to be linked
+#[derive(Debug)]
+enum Applied {
+    Unchanged,
+    Added(usize, config::Node),
+    Removed,
+}
+
+#[derive(Clone)]
+pub struct Peers {
+    agent: mpsc::Sender<Commands>,
+    connector: Arc<Connector>,
+    links: Links,
+}
+
+/// Tokio agent owning the status of every peer.
+/// The only way to interacte with it is to use [`Peers`].
+struct PeersHandler {
+    membership: Membership,
+    /// By identifier, in the order they were added
+    peers: BTreeMap<usize, PeerStatus>,
+    next_peer: usize,
+}
+
+impl Peers {
+    /// Track the nodes of the membership, not connected until reached
+    ///
+    /// The links are given to the system running when they are established.
+    pub fn new(membership: Membership, exchanger: Exchanger, links: Links) -> Self {
+        Self {
+            agent: PeersHandler::new(membership).spawn(),
+            connector: Arc::new(Connector::new(TcpConnector::new(exchanger))),
+            links,
+        }
+    }
+
+    /// Keep a link to every peer in the background, reconnecting the lost ones with backoff
+    ///
+    /// The peers added afterwards are supervised as well.
+    pub async fn supervise(&self) -> Result<()> {
+        for (peer, status) in self.get_all_by_id().await? {
+            self.link(peer, status.public_key, status.address);
+        }
+
+        Ok(())
+    }
+
+    /// Connect to the peer and give the link to the system, replacing it periodically
+    ///
+    /// A peer is connected as long as the last link was established, its key exchanged.
+    fn link(&self, peer: usize, public_key: exchange::PublicKey, address: String) {
+        let peers = self.clone();
+
+        tokio::spawn(async move {
+            let mut backoff = BACKOFF_MIN;
+
+            loop {
+                let connection = match tokio::time::timeout(
+                    CONNECT_TIMEOUT,
+                    peers.connector.connect(&public_key, address.as_str()),
+                )
+                .await
+                {
+                    Ok(Ok(connection)) => Some(connection),
+                    Ok(Err(err)) => {
+                        warn!(address = %address, "unable to connect: {}", err);
+                        None
+                    }
+                    Err(_) => {
+                        warn!(address = %address, "unable to connect: timed out");
+                        None
+                    }
+                };
+                let reached = connection.is_some();
+
+                let tracked = if reached {
+                    peers.seen(peer).await
+                } else {
+                    peers.lost(peer).await
+                };
+                // stop once removed, not giving the link to the system
+                if !matches!(tracked, Ok(true)) {
+                    break;
+                }
+
+                if let Some(connection) = connection {
+                    peers.links.give(connection).await;
+
+                    backoff = BACKOFF_MIN;
+                    tokio::time::sleep(LINK_REFRESH_INTERVAL).await;
+                } else {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                }
+            }
+        });
+    }
+
+    /// Apply the admin's change to the membership, returning if the peers changed
+    ///
+    /// An added peer is tracked and linked, a removed one is not anymore. The changes not
+    /// following the last applied one are ignored.
+    pub async fn apply(&self, sequence: sieve::Sequence, change: MembershipChange) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Apply {
+                sequence,
+                change,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        Ok(match rx.await.map_err(|_| Error::GoneOnRecv)?? {
+            Applied::Unchanged => false,
+            Applied::Added(peer, node) => {
+                self.link(peer, node.public_key, node.address);
+                true
+            }
+            Applied::Removed => true,
+        })
+    }
+
+    /// Return the nodes of the agreed membership
+    pub async fn members(&self) -> Result<Vec<config::Node>> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Members { resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+
+    /// Mark the peer as reached, returning if it is still tracked
+    async fn seen(&self, peer: usize) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Seen { peer, resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+
+    /// Mark the peer as unreachable, returning if it is still tracked
+    async fn lost(&self, peer: usize) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Lost { peer, resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+
+    /// Return the status of every peer
+    pub async fn get_all(&self) -> Result<Vec<PeerStatus>> {
+        Ok(self
+            .get_all_by_id()
+            .await?
+            .into_iter()
+            .map(|(_, status)| status)
+            .collect())
+    }
+
+    async fn get_all_by_id(&self) -> Result<Vec<(usize, PeerStatus)>> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::GetAll { resp: tx })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+}
+
+impl PeersHandler {
+    fn new(membership: Membership) -> Self {
+        let nodes = membership.nodes().to_vec();
+
+        let mut handler = Self {
+            membership,
+            peers: BTreeMap::new(),
+            next_peer: 0,
+        };
+        for node in nodes {
+            handler.add(node);
+        }
+
+        handler
+    }
+
+    fn spawn(mut self) -> mpsc::Sender<Commands> {
+        let (tx, mut rx) = mpsc::channel(32);
+
+        tokio::spawn(async move {
+            while let Some(cmd) = rx.recv().await {
+                match cmd {
+                    Commands::Apply {
+                        sequence,
+                        change,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.apply(sequence, change));
+                    }
+                    Commands::Members { resp } => {
+                        let _ = resp.send(self.membership.nodes().to_vec());
+                    }
+                    Commands::Seen { peer, resp } => {
+                        let _ = resp.send(self.seen(peer));
+                    }
+                    Commands::Lost { peer, resp } => {
+                        let _ = resp.send(self.lost(peer));
+                    }
+                    Commands::GetAll { resp } => {
+                        let _ = resp.send(
+                            self.peers
+                                .iter()
+                                .map(|(peer, status)| (*peer, status.clone()))
+                                .collect(),
+                        );
+                    }
+                }
+            }
+        });
+
+        tx
+    }
+
+    fn apply(&mut self, sequence: sieve::Sequence, change: MembershipChange) -> Result<Applied> {
+        let changed = self
+            .membership
+            .apply(sequence, change.clone())
+            .context(StoreMembership)?;
+        if !changed {
+            return Ok(Applied::Unchanged);
+        }
+
+        Ok(match change {
+            MembershipChange::Add {
+                public_key,
+                address,
+                rpc,
+                sign_key,
+            } => {
+                let node = config::Node {
+                    address,
+                    public_key,
+                    rpc: Some(rpc),
+                    sign_key: Some(sign_key),
+                };
+
+                match self.add(node.clone()) {
+                    Some(peer) => Applied::Added(peer, node),
+                    None => Applied::Unchanged,
+                }
+            }
+            MembershipChange::Remove { public_key } => {
+                self.remove(&public_key);
+                Applied::Removed
+            }
+        })
+    }
+
+    fn add(&mut self, node: config::Node) -> Option<usize> {
+        if self
+            .peers
+            .values()
+            .any(|status| status.public_key == node.public_key)
+        {
+            return None;
+        }
+
+        let peer = self.next_peer;
+        self.next_peer += 1;
+
+        info!(address = %node.address, "peer added");
+        self.peers.insert(
+            peer,
+            PeerStatus {
+                public_key: node.public_key,
+                address: node.address,
+                connected: false,
+                last_seen: None,
+                reconnect_attempts: 0,
+            },
+        );
+
+        Some(peer)
+    }
+
+    fn remove(&mut self, public_key: &exchange::PublicKey) {
+        self.peers.retain(|_, status| {
+            let keep = status.public_key != *public_key;
+            if !keep {
+                info!(address = %status.address, "peer removed");
+            }
+            keep
+        });
+    }
+
+    fn seen(&mut self, peer: usize) -> bool {
+        let status = match self.peers.get_mut(&peer) {
+            Some(status) => status,
+            None => return false,
+        };
+
+        if !status.connected {
+            info!(address = %status.address, "peer connected");
+        }
+
+        status.connected = true;
+        status.last_seen = Some(chrono::Utc::now());
+        status.reconnect_attempts = 0;
+
+        true
+    }
+
+    fn lost(&mut self, peer: usize) -> bool {
+        let status = match self.peers.get_mut(&peer) {
+            Some(status) => status,
+            None => return false,
+        };
+
+        if status.connected {
+            warn!(address = %status.address, "peer disconnected");
+        }
+
+        status.connected = false;
+        status.reconnect_attempts += 1;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(nodes: &[config::Node]) -> Peers {
+        Peers::new(
+            Membership::new(
+                nodes.to_vec(),
+                exchange::KeyPair::random().public().to_owned(),
+            ),
+            Exchanger::new(exchange::KeyPair::random()),
+            Links::default(),
+        )
+    }
+
+    fn node(address: &str) -> config::Node {
+        config::Node {
+            address: address.to_owned(),
+            public_key: exchange::KeyPair::random().public().to_owned(),
+            rpc: None,
+            sign_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn lost_peer_counts_attempts_until_seen() {
+        let peers = peers(&[node("127.0.0.1:1"), node("127.0.0.1:2")]);
+
+        peers.lost(0).await.expect("to mark as lost");
+        peers.lost(0).await.expect("to mark as lost");
+
+        let statuses = peers.get_all().await.expect("to get statuses");
+        assert!(!statuses[0].connected);
+        assert_eq!(2, statuses[0].reconnect_attempts);
+        assert!(statuses[0].last_seen.is_none());
+        assert_eq!(0, statuses[1].reconnect_attempts);
+
+        peers.seen(0).await.expect("to mark as seen");
+
+        let statuses = peers.get_all().await.expect("to get statuses");
+        assert!(statuses[0].connected);
+        assert_eq!(0, statuses[0].reconnect_attempts);
+        assert!(statuses[0].last_seen.is_some());
+    }
+
+    #[tokio::test]
+    async fn removed_peer_is_not_tracked() {
+        let first = node("127.0.0.1:1");
+        let peers = peers(std::slice::from_ref(&first));
+
+        assert!(peers
+            .apply(
+                1,
+                MembershipChange::Remove {
+                    public_key: first.public_key,
+                },
+            )
+            .await
+            .expect("to remove peer"));
+
+        assert!(peers.get_all().await.expect("to get statuses").is_empty());
+        assert!(peers.members().await.expect("to get members").is_empty());
+        assert!(!peers.seen(0).await.expect("to mark as seen"));
+    }
+}
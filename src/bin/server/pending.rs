@@ -0,0 +1,215 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use at2_node::Operation;
+use drop::crypto::sign;
+
+use super::journal;
+
+/// Delivered transaction, as its sequence, sender and operation
+pub type Transaction = (sieve::Sequence, sign::PublicKey, Operation);
+
+/// Delivered transactions not yet applied, queued by sender in sequence order
+///
+/// Only the first transaction of a sender can be applied, the following ones waiting for it
+/// to be committed. A sender is ready when it has something new to try, such as a new
+/// transaction or a created account it was waiting on.
+#[derive(Default)]
+pub struct Pending {
+    queues: HashMap<sign::PublicKey, BTreeMap<sieve::Sequence, (journal::Payload, Instant)>>,
+    ready: HashSet<sign::PublicKey>,
+    waiting_for_account: HashSet<sign::PublicKey>,
+    depth: usize,
+}
+
+impl Pending {
+    /// Queue a delivered transaction, making its sender ready
+    pub fn push(&mut self, msg: &journal::Payload) {
+        let sender = *msg.sender();
+
+        let previous = self
+            .queues
+            .entry(sender)
+            .or_default()
+            .insert(msg.sequence(), (msg.to_owned(), Instant::now()));
+        if previous.is_none() {
+            self.depth += 1;
+        }
+
+        self.waiting_for_account.remove(&sender);
+        self.ready.insert(sender);
+    }
+
+    /// Take any sender which is ready
+    pub fn next_ready(&mut self) -> Option<sign::PublicKey> {
+        let sender = *self.ready.iter().next()?;
+        self.ready.remove(&sender);
+
+        Some(sender)
+    }
+
+    /// Return the first queued transaction of the sender
+    pub fn first(&self, sender: &sign::PublicKey) -> Option<Transaction> {
+        self.queues
+            .get(sender)
+            .and_then(|queue| queue.iter().next())
+            .map(|(sequence, (msg, _))| (*sequence, *sender, msg.payload().to_owned()))
+    }
+
+    /// Drop the first queued transaction of the sender, once applied or failed
+    pub fn pop_first(&mut self, sender: &sign::PublicKey) {
+        if let Some(queue) = self.queues.get_mut(sender) {
+            if let Some(sequence) = queue.keys().next().copied() {
+                queue.remove(&sequence);
+                self.depth -= 1;
+            }
+            if queue.is_empty() {
+                self.queues.remove(sender);
+            }
+        }
+    }
+
+    /// Park the sender until an account is created, as its first transaction is missing one
+    pub fn wait_for_account(&mut self, sender: sign::PublicKey) {
+        self.waiting_for_account.insert(sender);
+    }
+
+    /// Make ready every sender waiting for an account
+    pub fn account_created(&mut self) {
+        self.ready.extend(self.waiting_for_account.drain());
+    }
+
+    /// Remove and return the transactions queued for longer than `ttl`
+    pub fn take_expired(&mut self, ttl: Duration) -> Vec<Transaction> {
+        let mut expired = Vec::new();
+
+        for (sender, queue) in self.queues.iter_mut() {
+            queue.retain(|sequence, (msg, when_added)| {
+                let keep = when_added.elapsed() <= ttl;
+                if !keep {
+                    expired.push((*sequence, *sender, msg.payload().to_owned()));
+                }
+                keep
+            });
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+        self.depth -= expired.len();
+
+        expired
+    }
+
+    /// Iterate over the queued payloads, as delivered
+    pub fn payloads(&self) -> impl Iterator<Item = &journal::Payload> {
+        self.queues
+            .values()
+            .flat_map(|queue| queue.values().map(|(msg, _)| msg))
+    }
+
+    /// Number of queued transactions
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Number of queued transactions of every sender
+    pub fn depths(&self) -> Vec<(sign::PublicKey, usize)> {
+        self.queues
+            .iter()
+            .map(|(sender, queue)| (*sender, queue.len()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use at2_node::{ThinTransaction, NATIVE_ASSET};
+
+    use super::*;
+
+    fn payload(sender: &sign::KeyPair, sequence: sieve::Sequence) -> journal::Payload {
+        let operation = Operation::Transfer(ThinTransaction {
+            recipient: sign::KeyPair::random().public(),
+            asset: NATIVE_ASSET.to_owned(),
+            amount: sequence.into(),
+            memo: None,
+        });
+        let signature = sender.sign(&operation).expect("sign failed");
+
+        journal::Payload::new(sender.public(), sequence, operation, signature)
+    }
+
+    #[test]
+    fn out_of_order_transactions_are_given_in_sequence() {
+        let sender = sign::KeyPair::random();
+        let mut pending = Pending::default();
+
+        for sequence in [3, 1, 2] {
+            pending.push(&payload(&sender, sequence));
+        }
+
+        assert_eq!(Some(sender.public()), pending.next_ready());
+        assert_eq!(None, pending.next_ready());
+        assert_eq!(3, pending.depth());
+
+        for sequence in 1..=3 {
+            let (first, _, _) = pending.first(&sender.public()).expect("to have a first");
+            assert_eq!(sequence, first);
+            pending.pop_first(&sender.public());
+        }
+
+        assert!(pending.first(&sender.public()).is_none());
+        assert_eq!(0, pending.depth());
+        assert!(pending.depths().is_empty());
+    }
+
+    #[test]
+    fn depths_are_counted_by_sender() {
+        let (first, second) = (sign::KeyPair::random(), sign::KeyPair::random());
+        let mut pending = Pending::default();
+
+        pending.push(&payload(&first, 1));
+        pending.push(&payload(&first, 2));
+        pending.push(&payload(&second, 4));
+        // delivered twice
+        pending.push(&payload(&second, 4));
+
+        let mut depths = pending.depths();
+        depths.sort_by_key(|(_, depth)| *depth);
+        assert_eq!(vec![(second.public(), 1), (first.public(), 2)], depths);
+        assert_eq!(3, pending.depth());
+
+        pending.pop_first(&first.public());
+        pending.pop_first(&second.public());
+        assert_eq!(vec![(first.public(), 1)], pending.depths());
+    }
+
+    #[test]
+    fn sender_waiting_for_account_is_ready_once_created() {
+        let sender = sign::KeyPair::random();
+        let mut pending = Pending::default();
+
+        pending.push(&payload(&sender, 1));
+        let ready = pending.next_ready().expect("sender to be ready");
+        pending.wait_for_account(ready);
+        assert_eq!(None, pending.next_ready());
+
+        pending.account_created();
+        assert_eq!(Some(sender.public()), pending.next_ready());
+    }
+
+    #[test]
+    fn expired_transactions_are_removed() {
+        let sender = sign::KeyPair::random();
+        let mut pending = Pending::default();
+
+        pending.push(&payload(&sender, 1));
+        pending.push(&payload(&sender, 2));
+
+        assert!(pending.take_expired(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(2, pending.take_expired(Duration::ZERO).len());
+        assert_eq!(0, pending.depth());
+        assert!(pending.depths().is_empty());
+    }
+}
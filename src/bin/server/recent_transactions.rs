@@ -1,10 +1,12 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
 use at2_node::{FullTransaction, ThinTransaction, TransactionState};
 use drop::crypto::sign;
 use tokio::sync::{mpsc, oneshot};
 
 const LATEST_TRANSACTIONS_MAX_SIZE: usize = 10;
+/// How many transactions are kept, older ones being forgotten
+const TRANSACTIONS_MAX_SIZE: usize = 100_000;
 
 #[derive(snafu::Snafu, Debug)]
 pub enum Error {
@@ -21,18 +23,28 @@ enum Commands {
     Put {
         sender: Box<sign::PublicKey>,
         sender_sequence: sieve::Sequence,
-        thin: ThinTransaction,
+        outputs: Vec<ThinTransaction>,
         resp: oneshot::Sender<Result<()>>,
     },
     Update {
         sender: Box<sign::PublicKey>,
         sender_sequence: sieve::Sequence,
         state: TransactionState,
-        resp: oneshot::Sender<()>,
+        resp: oneshot::Sender<Option<Vec<FullTransaction>>>,
+    },
+    Get {
+        sender: Box<sign::PublicKey>,
+        sender_sequence: sieve::Sequence,
+        resp: oneshot::Sender<Option<Vec<FullTransaction>>>,
     },
     GetAll {
         resp: oneshot::Sender<Vec<FullTransaction>>,
     },
+    Watch {
+        sender: Box<sign::PublicKey>,
+        sender_sequence: sieve::Sequence,
+        resp: oneshot::Sender<mpsc::UnboundedReceiver<FullTransaction>>,
+    },
 }
 
 #[derive(Clone)]
@@ -42,7 +54,20 @@ pub struct RecentTransactions {
 
 /// Tokio agent owning the recent transactions.
 /// The only way to interacte with it is to use [`RecentTransactions`].
-struct RecentTransactionsHandler(VecDeque<FullTransaction>);
+///
+/// Only the last [`TRANSACTIONS_MAX_SIZE`] seen transactions are kept, and only in memory, so
+/// none are known after a restart.
+struct RecentTransactionsHandler {
+    /// Outputs of the recently seen transactions, indexed by sender and sequence
+    transactions: HashMap<(sign::PublicKey, sieve::Sequence), Vec<FullTransaction>>,
+    /// Order in which the transactions were put, to forget the oldest
+    seen: VecDeque<(sign::PublicKey, sieve::Sequence)>,
+    /// Index of the latest put transactions
+    latest: VecDeque<(sign::PublicKey, sieve::Sequence)>,
+    /// Channels to notify on state changes, until the transaction resolves
+    watchers:
+        HashMap<(sign::PublicKey, sieve::Sequence), Vec<mpsc::UnboundedSender<FullTransaction>>>,
+}
 
 impl RecentTransactions {
     pub fn new() -> Self {
@@ -51,12 +76,15 @@ impl RecentTransactions {
         }
     }
 
-    /// Add a new transaction
+    /// Add a new transaction, with every output it transfers
+    ///
+    /// A transaction still pending takes the outputs last put, as the ones requested can differ
+    /// from the delivered ones, put again once delivered.
     pub async fn put(
         &self,
         sender: Box<sign::PublicKey>,
         sender_sequence: sieve::Sequence,
-        thin: ThinTransaction,
+        outputs: Vec<ThinTransaction>,
     ) -> Result<()> {
         let (tx, rx) = oneshot::channel();
 
@@ -64,7 +92,7 @@ impl RecentTransactions {
             .send(Commands::Put {
                 sender,
                 sender_sequence,
-                thin,
+                outputs,
                 resp: tx,
             })
             .await
@@ -74,12 +102,14 @@ impl RecentTransactions {
     }
 
     /// Update an already put transaction, to resolve its state
+    ///
+    /// Return the outputs of the updated transaction, if still known.
     pub async fn update(
         &self,
         sender: Box<sign::PublicKey>,
         sender_sequence: sieve::Sequence,
         state: TransactionState,
-    ) -> Result<()> {
+    ) -> Result<Option<Vec<FullTransaction>>> {
         let (tx, rx) = oneshot::channel();
 
         self.agent
@@ -95,7 +125,28 @@ impl RecentTransactions {
         rx.await.map_err(|_| Error::GoneOnRecv)
     }
 
-    /// Return the recently seen transactions
+    /// Return the outputs of the transaction sent by `sender` with the given sequence, if
+    /// recently seen
+    pub async fn get(
+        &self,
+        sender: Box<sign::PublicKey>,
+        sender_sequence: sieve::Sequence,
+    ) -> Result<Option<Vec<FullTransaction>>> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Get {
+                sender,
+                sender_sequence,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+
+    /// Return the outputs of the recently seen transactions
     pub async fn get_all(&self) -> Result<Vec<FullTransaction>> {
         let (tx, rx) = oneshot::channel();
 
@@ -106,11 +157,44 @@ impl RecentTransactions {
 
         rx.await.map_err(|_| Error::GoneOnRecv)
     }
+
+    /// Follow the states taken by the transaction sent by `sender` with the given sequence
+    ///
+    /// The current state is sent first, if the transaction is already known. The channel is
+    /// closed after sending a resolved state. Only the first output is sent, every output
+    /// sharing the state of the transaction.
+    pub async fn watch(
+        &self,
+        sender: Box<sign::PublicKey>,
+        sender_sequence: sieve::Sequence,
+    ) -> Result<mpsc::UnboundedReceiver<FullTransaction>> {
+        let (tx, rx) = oneshot::channel();
+
+        self.agent
+            .send(Commands::Watch {
+                sender,
+                sender_sequence,
+                resp: tx,
+            })
+            .await
+            .map_err(|_| Error::GoneOnSend)?;
+
+        rx.await.map_err(|_| Error::GoneOnRecv)
+    }
+}
+
+fn is_resolved(state: &TransactionState) -> bool {
+    !matches!(state, TransactionState::Pending)
 }
 
 impl RecentTransactionsHandler {
     fn new() -> Self {
-        Self(VecDeque::with_capacity(LATEST_TRANSACTIONS_MAX_SIZE))
+        Self {
+            transactions: HashMap::new(),
+            seen: VecDeque::new(),
+            latest: VecDeque::with_capacity(LATEST_TRANSACTIONS_MAX_SIZE),
+            watchers: HashMap::new(),
+        }
     }
 
     fn spawn(mut self) -> mpsc::Sender<Commands> {
@@ -122,10 +206,10 @@ impl RecentTransactionsHandler {
                     Commands::Put {
                         sender,
                         sender_sequence,
-                        thin,
+                        outputs,
                         resp,
                     } => {
-                        let _ = resp.send(self.put(*sender, sender_sequence, thin));
+                        let _ = resp.send(self.put(*sender, sender_sequence, outputs));
                     }
                     Commands::Update {
                         sender,
@@ -133,12 +217,25 @@ impl RecentTransactionsHandler {
                         state,
                         resp,
                     } => {
-                        self.update(*sender, sender_sequence, state);
-                        let _ = resp.send(());
+                        let _ = resp.send(self.update(*sender, sender_sequence, state));
+                    }
+                    Commands::Get {
+                        sender,
+                        sender_sequence,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.get(&sender, sender_sequence));
                     }
                     Commands::GetAll { resp } => {
                         let _ = resp.send(self.get_all());
                     }
+                    Commands::Watch {
+                        sender,
+                        sender_sequence,
+                        resp,
+                    } => {
+                        let _ = resp.send(self.watch(*sender, sender_sequence));
+                    }
                 }
             }
         });
@@ -150,31 +247,45 @@ impl RecentTransactionsHandler {
         &mut self,
         sender: sign::PublicKey,
         sender_sequence: sieve::Sequence,
-        thin: ThinTransaction,
+        outputs: Vec<ThinTransaction>,
     ) -> Result<()> {
-        // NOP if already existing
-        if self
-            .0
-            .iter()
-            .any(|tx| tx.sender_sequence == sender_sequence && tx.sender == sender)
-        {
+        let timestamp = chrono::Utc::now();
+        let full = outputs
+            .into_iter()
+            .map(|thin| FullTransaction {
+                timestamp,
+                sender,
+                sender_sequence,
+                recipient: thin.recipient,
+                asset: thin.asset,
+                amount: thin.amount,
+                state: TransactionState::Pending,
+                memo: thin.memo,
+            })
+            .collect::<Vec<_>>();
+
+        // NOP if already resolved
+        if let Some(known) = self.transactions.get_mut(&(sender, sender_sequence)) {
+            if !known.iter().any(|tx| is_resolved(&tx.state)) {
+                *known = full;
+            }
             return Ok(());
         }
 
-        let full = FullTransaction {
-            timestamp: chrono::Utc::now(),
-            sender,
-            sender_sequence,
-            recipient: thin.recipient,
-            amount: thin.amount,
-            state: TransactionState::Pending,
-        };
+        if self.latest.len() == LATEST_TRANSACTIONS_MAX_SIZE {
+            self.latest.pop_front();
+        }
 
-        if self.0.len() == LATEST_TRANSACTIONS_MAX_SIZE {
-            self.0.pop_front();
+        if self.seen.len() == TRANSACTIONS_MAX_SIZE {
+            if let Some(oldest) = self.seen.pop_front() {
+                self.transactions.remove(&oldest);
+            }
         }
 
-        self.0.push_back(full);
+        self.latest.push_back((sender, sender_sequence));
+        self.seen.push_back((sender, sender_sequence));
+        self.notify(&full);
+        self.transactions.insert((sender, sender_sequence), full);
 
         Ok(())
     }
@@ -184,25 +295,91 @@ impl RecentTransactionsHandler {
         sender: sign::PublicKey,
         sender_sequence: sieve::Sequence,
         state: TransactionState,
-    ) {
+    ) -> Option<Vec<FullTransaction>> {
         // NOP if not found as the transaction may resolve late
-        if let Some(tx) = self
-            .0
-            .iter_mut()
-            .rfind(|tx| tx.sender_sequence == sender_sequence && tx.sender == sender)
+        let outputs = self.transactions.get_mut(&(sender, sender_sequence))?;
+        outputs.iter_mut().for_each(|tx| tx.state = state.clone());
+
+        let outputs = outputs.clone();
+        self.notify(&outputs);
+
+        Some(outputs)
+    }
+
+    /// Send the first output to the watchers, forgetting them when it is resolved
+    fn notify(&mut self, outputs: &[FullTransaction]) {
+        let tx = match outputs.first() {
+            None => return,
+            Some(tx) => tx,
+        };
+        let key = (tx.sender, tx.sender_sequence);
+
+        if is_resolved(&tx.state) {
+            if let Some(watchers) = self.watchers.remove(&key) {
+                watchers.iter().for_each(|watcher| {
+                    let _ = watcher.send(tx.clone());
+                });
+            }
+        } else if let Some(watchers) = self.watchers.get_mut(&key) {
+            watchers.retain(|watcher| watcher.send(tx.clone()).is_ok());
+        }
+    }
+
+    fn watch(
+        &mut self,
+        sender: sign::PublicKey,
+        sender_sequence: sieve::Sequence,
+    ) -> mpsc::UnboundedReceiver<FullTransaction> {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        // drop watchers which went away in the meantime
+        self.watchers
+            .retain(|_, watchers| watchers.iter().any(|watcher| !watcher.is_closed()));
+
+        match self
+            .transactions
+            .get(&(sender, sender_sequence))
+            .and_then(|outputs| outputs.first())
         {
-            tx.state = state;
+            Some(full) if is_resolved(&full.state) => {
+                let _ = tx.send(full.clone());
+            }
+            current => {
+                if let Some(full) = current {
+                    let _ = tx.send(full.clone());
+                }
+                self.watchers
+                    .entry((sender, sender_sequence))
+                    .or_default()
+                    .push(tx);
+            }
         }
+
+        rx
+    }
+
+    fn get(
+        &self,
+        sender: &sign::PublicKey,
+        sender_sequence: sieve::Sequence,
+    ) -> Option<Vec<FullTransaction>> {
+        self.transactions.get(&(*sender, sender_sequence)).cloned()
     }
 
     fn get_all(&self) -> Vec<FullTransaction> {
-        self.0.clone().into()
+        self.latest
+            .iter()
+            .filter_map(|key| self.transactions.get(key))
+            .flatten()
+            .cloned()
+            .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use at2_node::NATIVE_ASSET;
 
     #[tokio::test]
     async fn put_transactions_show_in_get_all() {
@@ -214,17 +391,21 @@ mod tests {
         let txs = [
             ThinTransaction {
                 amount: 10,
+                asset: NATIVE_ASSET.to_owned(),
                 recipient,
+                memo: None,
             },
             ThinTransaction {
                 amount: 3,
+                asset: NATIVE_ASSET.to_owned(),
                 recipient: sender,
+                memo: Some("invoice 42".to_owned()),
             },
         ];
 
         for (tx, seq) in txs.iter().zip(1..) {
             recent_transactions
-                .put(Box::new(sender), seq, tx.clone())
+                .put(Box::new(sender), seq, vec![tx.clone()])
                 .await
                 .expect("to put transaction");
         }
@@ -241,9 +422,207 @@ mod tests {
             .for_each(|((thin, full), seq)| {
                 assert_eq!(sender, full.sender);
                 assert_eq!(seq, full.sender_sequence);
+                assert_eq!(thin.asset, full.asset);
                 assert_eq!(thin.amount, full.amount);
                 assert_eq!(thin.recipient, full.recipient);
+                assert_eq!(thin.memo, full.memo);
                 assert_eq!(TransactionState::Pending, full.state);
             });
     }
+
+    #[tokio::test]
+    async fn updated_transaction_is_found_after_leaving_latest() {
+        let recent_transactions = RecentTransactions::new();
+
+        let sender = sign::KeyPair::random().public();
+        let thin = ThinTransaction {
+            amount: 10,
+            asset: NATIVE_ASSET.to_owned(),
+            recipient: sign::KeyPair::random().public(),
+            memo: None,
+        };
+
+        for seq in 1..=(LATEST_TRANSACTIONS_MAX_SIZE as sieve::Sequence + 1) {
+            recent_transactions
+                .put(Box::new(sender), seq, vec![thin.clone()])
+                .await
+                .expect("to put transaction");
+        }
+
+        recent_transactions
+            .update(Box::new(sender), 1, TransactionState::Success)
+            .await
+            .expect("to update transaction");
+
+        let recent_txs = recent_transactions
+            .get_all()
+            .await
+            .expect("to get recent txs");
+        assert!(recent_txs.iter().all(|tx| tx.sender_sequence != 1));
+
+        let outputs = recent_transactions
+            .get(Box::new(sender), 1)
+            .await
+            .expect("to get transaction")
+            .expect("transaction to be found");
+        let full = &outputs[0];
+
+        assert_eq!(thin.amount, full.amount);
+        assert_eq!(thin.recipient, full.recipient);
+        assert_eq!(TransactionState::Success, full.state);
+    }
+
+    #[tokio::test]
+    async fn batch_outputs_keep_their_memo() {
+        let recent_transactions = RecentTransactions::new();
+
+        let sender = sign::KeyPair::random().public();
+        let outputs = ["invoice 1", "invoice 2"]
+            .iter()
+            .map(|memo| ThinTransaction {
+                amount: 10,
+                asset: NATIVE_ASSET.to_owned(),
+                recipient: sign::KeyPair::random().public(),
+                memo: Some(memo.to_string()),
+            })
+            .collect::<Vec<_>>();
+
+        recent_transactions
+            .put(Box::new(sender), 1, outputs.clone())
+            .await
+            .expect("to put transaction");
+        let updated = recent_transactions
+            .update(Box::new(sender), 1, TransactionState::Success)
+            .await
+            .expect("to update transaction")
+            .expect("transaction to be found");
+
+        assert_eq!(outputs.len(), updated.len());
+        outputs.iter().zip(updated.iter()).for_each(|(thin, full)| {
+            assert_eq!(thin.recipient, full.recipient);
+            assert_eq!(thin.memo, full.memo);
+            assert_eq!(TransactionState::Success, full.state);
+        });
+    }
+
+    #[tokio::test]
+    async fn pending_transaction_takes_last_put_outputs() {
+        let recent_transactions = RecentTransactions::new();
+
+        let sender = sign::KeyPair::random().public();
+        let thin = |amount| ThinTransaction {
+            amount,
+            asset: NATIVE_ASSET.to_owned(),
+            recipient: sign::KeyPair::random().public(),
+            memo: None,
+        };
+
+        for amount in [10, 20] {
+            recent_transactions
+                .put(Box::new(sender), 1, vec![thin(amount)])
+                .await
+                .expect("to put transaction");
+        }
+        recent_transactions
+            .update(Box::new(sender), 1, TransactionState::Success)
+            .await
+            .expect("to update transaction");
+        recent_transactions
+            .put(Box::new(sender), 1, vec![thin(30)])
+            .await
+            .expect("to put transaction");
+
+        let outputs = recent_transactions
+            .get(Box::new(sender), 1)
+            .await
+            .expect("to get transaction")
+            .expect("transaction to be found");
+        assert_eq!(20, outputs[0].amount);
+        assert_eq!(TransactionState::Success, outputs[0].state);
+        assert_eq!(
+            1,
+            recent_transactions
+                .get_all()
+                .await
+                .expect("to get recent txs")
+                .len()
+        );
+    }
+
+    #[test]
+    fn oldest_transaction_is_forgotten() {
+        let mut recent_transactions = RecentTransactionsHandler::new();
+
+        let sender = sign::KeyPair::random().public();
+        let thin = ThinTransaction {
+            amount: 10,
+            asset: NATIVE_ASSET.to_owned(),
+            recipient: sign::KeyPair::random().public(),
+            memo: None,
+        };
+
+        for seq in 1..=(TRANSACTIONS_MAX_SIZE as sieve::Sequence + 1) {
+            recent_transactions
+                .put(sender, seq, vec![thin.clone()])
+                .expect("to put transaction");
+        }
+
+        assert!(recent_transactions.get(&sender, 1).is_none());
+        assert!(recent_transactions.get(&sender, 2).is_some());
+        assert_eq!(
+            TRANSACTIONS_MAX_SIZE,
+            recent_transactions.transactions.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn watched_transaction_closes_once_resolved() {
+        let recent_transactions = RecentTransactions::new();
+
+        let sender = sign::KeyPair::random().public();
+        let thin = ThinTransaction {
+            amount: 10,
+            asset: NATIVE_ASSET.to_owned(),
+            recipient: sign::KeyPair::random().public(),
+            memo: None,
+        };
+
+        let mut watcher = recent_transactions
+            .watch(Box::new(sender), 1)
+            .await
+            .expect("to watch transaction");
+
+        recent_transactions
+            .put(Box::new(sender), 1, vec![thin])
+            .await
+            .expect("to put transaction");
+        recent_transactions
+            .update(Box::new(sender), 1, TransactionState::Success)
+            .await
+            .expect("to update transaction");
+
+        let states = [
+            watcher.recv().await.map(|tx| tx.state),
+            watcher.recv().await.map(|tx| tx.state),
+            watcher.recv().await.map(|tx| tx.state),
+        ];
+        assert_eq!(
+            [
+                Some(TransactionState::Pending),
+                Some(TransactionState::Success),
+                None
+            ],
+            states
+        );
+
+        let mut late_watcher = recent_transactions
+            .watch(Box::new(sender), 1)
+            .await
+            .expect("to watch transaction");
+        assert_eq!(
+            Some(TransactionState::Success),
+            late_watcher.recv().await.map(|tx| tx.state)
+        );
+        assert!(late_watcher.recv().await.is_none());
+    }
 }
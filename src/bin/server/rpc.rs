@@ -1,38 +1,52 @@
-use std::{
-    cmp::Reverse,
-    collections::BinaryHeap,
-    fmt,
-    time::{Duration, Instant},
-};
+use std::{collections::BTreeMap, fmt, pin::Pin, time::Duration};
 
 use at2_node::{
+    is_issuable_asset,
     proto::{self, *},
-    ThinTransaction, TransactionState,
+    state, FailureReason, FullTransaction, HistoryDirection, MembershipChange, Operation,
+    SignedReply, ThinTransaction, TransactionState, BATCH_MAX_SIZE, CREATE_ACCOUNT_SEQUENCE,
+    MEMO_MAX_SIZE, NATIVE_ASSET,
 };
-use contagion::{Contagion, ContagionConfig, ContagionMessage};
 use drop::{
     crypto::{
         key::exchange::{self, Exchanger},
         sign,
     },
-    net::{ConnectorExt, ResolveConnector, TcpConnector, TcpListener},
-    system::{AllSampler, Handle, NetworkSender, System, SystemManager},
+    system::Handle,
+};
+use futures::{future, stream, Stream, StreamExt};
+use prost::Message as _;
+use snafu::{ensure, ResultExt, Snafu};
+use tokio::{
+    net,
+    sync::{broadcast, watch},
+    task,
 };
-use futures::{future, StreamExt};
-use murmur::MurmurConfig;
-use sieve::SieveConfig;
-use snafu::{ResultExt, Snafu};
-use tokio::net;
 use tonic::Response;
 use tracing::{info, warn};
 
 use super::{
-    accounts::{self, Accounts},
+    accounts::{self, account, snapshot, Accounts},
     config,
+    history::{self, History},
+    journal::{self, Journal},
+    membership::Membership,
+    network::{self, Network},
+    peers::{self, Peers},
+    pending::{self, Pending},
     recent_transactions::{self, RecentTransactions},
+    sync::{self, CatchUp},
 };
 
 const TRANSACTION_TTL: Duration = Duration::from_secs(60);
+/// How long to wait for a transaction to resolve before giving up, a bit more than its TTL
+const TRANSACTION_WAIT_TIMEOUT: Duration = Duration::from_secs(70);
+/// How long to wait to catch up with the network before refusing to serve the balances
+const CATCH_UP_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of committed transactions kept for slow subscribers
+const COMMITTED_TRANSACTIONS_BUFFER_SIZE: usize = 128;
+/// Number of applied payloads kept in the journal before compacting it
+const JOURNAL_COMPACTION_SIZE: usize = 1024;
 
 #[derive(Snafu, Debug)]
 pub enum ProtoError {
@@ -44,196 +58,909 @@ pub enum ProtoError {
 
 #[derive(Snafu, Debug)]
 pub enum ProcessTransactionError {
+    #[snafu(display("account created with sequence {}", sequence))]
+    CreateAccountSequence { sequence: sieve::Sequence },
+    #[snafu(display("batch of {} outputs", size))]
+    BatchSize { size: usize },
+    #[snafu(display("memo longer than {} bytes", MEMO_MAX_SIZE))]
+    MemoSize,
+    #[snafu(display("issued by non issuer"))]
+    NotIssuer,
+    #[snafu(display("unissuable asset: {}", asset))]
+    InvalidAsset { asset: String },
+    #[snafu(display("membership changed by non admin"))]
+    NotAdmin,
+    #[snafu(display("signed for sequence {} but sent with {}", signed, sequence))]
+    SignedSequence {
+        signed: sieve::Sequence,
+        sequence: sieve::Sequence,
+    },
     #[snafu(display("handle by acounts: {}", source))]
     ProcessTxForAccounts { source: accounts::Error },
     #[snafu(display("handle by recent transactions: {}", source))]
     ProcessTxForRecent { source: recent_transactions::Error },
+    #[snafu(display("handle by peers: {}", source))]
+    ProcessTxForPeers { source: peers::Error },
+}
+
+impl ProcessTransactionError {
+    /// If the transaction can succeed later, when more are processed
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::ProcessTxForAccounts {
+                source: accounts::Error::NoSuchAccount { .. },
+            } => true,
+            Self::ProcessTxForAccounts {
+                source:
+                    accounts::Error::AccountModification {
+                        source: account::Error::InconsecutiveSequence { last, sequence },
+                    },
+            } => sequence > last,
+            _ => false,
+        }
+    }
+
+    /// If the transaction waits on its sender's account to be created
+    fn is_missing_account(&self) -> bool {
+        matches!(
+            self,
+            Self::ProcessTxForAccounts {
+                source: accounts::Error::NoSuchAccount { .. },
+            }
+        )
+    }
+
+    fn failure_reason(&self) -> FailureReason {
+        match self {
+            Self::ProcessTxForAccounts {
+                source: accounts::Error::AccountModification { source },
+            } => match source {
+                account::Error::InconsecutiveSequence { .. } => FailureReason::SequenceGap,
+                account::Error::Overflow => FailureReason::Overflow,
+                account::Error::Underflow => FailureReason::InsufficientFunds,
+            },
+            Self::ProcessTxForAccounts {
+                source: accounts::Error::NoSuchReceiver { .. },
+            } => FailureReason::NoSuchAccount,
+            _ => FailureReason::Other,
+        }
+    }
 }
 
 #[derive(Snafu, Debug)]
 pub enum Error {
     #[snafu(display("new service: {}", source))]
-    ServiceNew { source: drop::net::ListenerError },
-    #[snafu(display("service: process transaction: {}", source))]
-    ProcessTransaction { source: ProcessTransactionError },
+    ServiceNew { source: network::Error },
+    #[snafu(display("service: consensus: {}", source))]
+    ServiceConsensus { source: config::Error },
+    #[snafu(display("service: network: {}", source))]
+    ServiceNetwork { source: network::Error },
+    #[snafu(display("service: replay journal: {}", source))]
+    Replay { source: accounts::Error },
+    #[snafu(display("service: peers: {}", source))]
+    ServicePeers { source: peers::Error },
+    #[snafu(display("service: journal: {}", source))]
+    JournalAppend { source: journal::Error },
+    #[snafu(display("service: compact journal: {}", source))]
+    JournalCompact { source: journal::Error },
+}
+
+/// Accounts allowed to run the privileged operations, if any
+#[derive(Debug, Clone, Copy)]
+pub struct Authorities {
+    /// Allowed to issue new assets
+    pub issuer: Option<sign::PublicKey>,
+    /// Allowed to change the nodes of the network
+    pub admin: Option<sign::PublicKey>,
+}
+
+/// What the node restores on start, kept across restarts if stored
+pub struct State {
+    pub accounts: Accounts,
+    /// Committed transactions of every account
+    pub history: History,
+    /// Log of the delivered payloads with the ones it contains, if stored
+    pub journal: Option<(Journal, Vec<journal::Payload>)>,
+    /// Other nodes of the network
+    pub membership: Membership,
 }
 
 #[derive(Clone)]
 pub struct Service {
-    handle: contagion::ContagionHandle<
-        ThinTransaction,
-        NetworkSender<ContagionMessage<ThinTransaction>>,
-        contagion::Fixed,
-    >,
+    network: Network,
     accounts: Accounts,
     recent_transactions: RecentTransactions,
+    history: History,
+    committed: broadcast::Sender<FullTransaction>,
+    authorities: Authorities,
+    peers: Peers,
+    /// Delivered payloads not yet applied, given to the nodes catching up
+    pending_payloads: watch::Receiver<Vec<journal::Payload>>,
+    /// Number of delivered payloads not yet applied, by sender
+    pending_depths: watch::Receiver<Vec<(sign::PublicKey, usize)>>,
+    /// If caught up with the network and taking part in the broadcast, before which balances
+    /// are not served nor operations broadcasted
+    synced: watch::Receiver<bool>,
+    /// Signs the state roots given to the clients
+    keypair: sign::KeyPair,
 }
 
 impl Service {
+    /// Start the node, returned with the task processing the delivered payloads
+    ///
+    /// The task only ends when unable to go on, such as when failing to journal a payload.
     pub async fn new(
         listener_addr: impl net::ToSocketAddrs + fmt::Display,
-        network_keypair: exchange::KeyPair,
-        network: Vec<config::Node>,
-    ) -> Result<Self, Error> {
-        let network_size = network.len();
+        keys: config::ConfigKeys,
+        consensus: config::ConfigConsensus,
+        authorities: Authorities,
+        state: State,
+    ) -> Result<(Self, task::JoinHandle<Result<(), Error>>), Error> {
+        let exchanger = Exchanger::new(exchange::KeyPair::new(keys.network));
 
-        let exchanger = Exchanger::new(network_keypair);
+        let nodes = state.membership.nodes();
+        let catch_up = CatchUp {
+            nodes: nodes
+                .iter()
+                .filter(|node| node.rpc.is_some() && node.sign_key.is_some())
+                .cloned()
+                .collect(),
+            threshold: consensus
+                .resolve(nodes.len())
+                .context(ServiceConsensus)?
+                .fault_tolerance
+                + 1,
+        };
 
-        let listener = TcpListener::new(listener_addr, exchanger.clone())
+        let (network, links) = Network::listen(listener_addr, exchanger.clone(), consensus)
             .await
             .context(ServiceNew)?;
+        let peers = Peers::new(state.membership, exchanger, links);
 
-        let connector = ResolveConnector::new(TcpConnector::new(exchanger)).retry();
-        // TODO readd connections if dropped
-        let mut system = System::new_with_connector_zipped(
-            &connector,
-            network
-                .into_iter()
-                .map(|node| (node.public_key, node.address)),
-        )
-        .await;
+        let (pending_payloads_sender, pending_payloads) = watch::channel(Vec::new());
+        let (pending_depths_sender, pending_depths) = watch::channel(Vec::new());
+        let (synced_sender, synced) = watch::channel(false);
 
-        let listener_errors = system.add_listener(listener).await;
-        tokio::spawn(async move {
-            listener_errors
-                .for_each(|err| {
-                    warn!("listener error: {}", err);
-                    future::ready(())
-                })
-                .await
-        });
+        let mut service = Self {
+            network,
+            accounts: state.accounts,
+            recent_transactions: RecentTransactions::new(),
+            history: state.history,
+            committed: broadcast::channel(COMMITTED_TRANSACTIONS_BUFFER_SIZE).0,
+            authorities,
+            peers,
+            pending_payloads,
+            pending_depths,
+            synced,
+            keypair: sign::KeyPair::from(keys.sign),
+        };
+        let (journal, delivered) = match state.journal {
+            Some((journal, delivered)) => (Some(journal), delivered),
+            None => (None, Vec::new()),
+        };
+        let pending = service.replay(delivered).await?;
 
-        let manager = SystemManager::new(system);
-
-        let contagion = Contagion::new(
-            contagion::Fixed::new_local(),
-            ContagionConfig {
-                sieve: SieveConfig {
-                    sieve_sample_size: network_size,
-                    echo_threshold: network_size,
-                    murmur: MurmurConfig {
-                        murmur_gossip_size: network_size,
-                        ..Default::default()
-                    },
-                },
-                contagion_sample_size: network_size,
-                ready_threshold: network_size,
-            },
+        // nothing to catch up with if no other node can be asked
+        let catch_up = if catch_up.nodes.is_empty() {
+            None
+        } else if catch_up.nodes.len() < catch_up.threshold {
+            warn!(
+                nodes = catch_up.nodes.len(),
+                threshold = catch_up.threshold,
+                "not enough nodes with an RPC address and a signing key to catch up"
+            );
+            None
+        } else {
+            Some(catch_up)
+        };
+
+        let processing = service.spawn(
+            journal,
+            pending,
+            catch_up,
+            synced_sender,
+            pending_payloads_sender,
+            pending_depths_sender,
         );
 
-        let sampler = AllSampler::default();
-        let mut handle = manager.run(contagion, sampler, num_cpus::get()).await;
+        Ok((service, processing))
+    }
 
-        let handle_errors = handle.errors();
-        tokio::spawn(async move {
-            if let Some(stream) = handle_errors {
-                stream
-                    .for_each(|err| {
-                        warn!("handle error: {}", err);
-                        future::ready(())
-                    })
+    /// Reprocess the payloads delivered before a restart
+    async fn replay(&mut self, delivered: Vec<journal::Payload>) -> Result<Pending, Error> {
+        let mut pending = Pending::default();
+
+        self.queue_unapplied(&delivered, &mut pending).await?;
+        self.process_ready(&mut pending).await;
+
+        Ok(pending)
+    }
+
+    /// Queue the delivered payloads not yet applied to the accounts
+    ///
+    /// As the membership is stored apart, a change already applied to the accounts is given
+    /// again to the peers, which only apply it if missed, such as when crashing.
+    async fn queue_unapplied(
+        &mut self,
+        delivered: &[journal::Payload],
+        pending: &mut Pending,
+    ) -> Result<(), Error> {
+        for msg in delivered.iter() {
+            let already_applied = match self
+                .accounts
+                .get_last_sequence(Box::new(*msg.sender()))
+                .await
+            {
+                Ok(last_sequence) => msg.sequence() <= last_sequence,
+                Err(accounts::Error::NoSuchAccount { .. }) => false,
+                Err(err) => return Err(err).context(Replay),
+            };
+
+            if !already_applied {
+                pending.push(msg);
+            } else if let Operation::Membership { change, .. } = msg.payload() {
+                self.peers
+                    .apply(msg.sequence(), change.to_owned())
                     .await
+                    .context(ServicePeers)?;
             }
-        });
-
-        let service = Self {
-            handle: handle.processor_handle(),
-            accounts: Accounts::new(),
-            recent_transactions: RecentTransactions::new(),
-        };
-        service.spawn();
+        }
 
-        Ok(service)
+        Ok(())
     }
 
-    fn spawn(&self) {
+    /// Process the delivered payloads, after catching up with the network if asked to
+    ///
+    /// The node takes part in the broadcast before catching up, to not miss any payload
+    /// meanwhile, the ones it delivers being applied on top of the agreed ledger. The clients
+    /// are only served once caught up.
+    fn spawn(
+        &self,
+        mut journal: Option<Journal>,
+        mut pending: Pending,
+        catch_up: Option<CatchUp>,
+        synced: watch::Sender<bool>,
+        pending_payloads: watch::Sender<Vec<journal::Payload>>,
+        pending_depths: watch::Sender<Vec<(sign::PublicKey, usize)>>,
+    ) -> task::JoinHandle<Result<(), Error>> {
         let mut service = self.clone();
 
         tokio::spawn(async move {
-            let mut to_process = BinaryHeap::new();
+            let members = service.peers.members().await.context(ServicePeers)?;
+            service
+                .network
+                .start(&members)
+                .await
+                .context(ServiceNetwork)?;
+            service.peers.supervise().await.context(ServicePeers)?;
+
+            let mut handles = service.network.handles();
+
+            if let Some(catch_up) = catch_up {
+                let mut buffered = Vec::new();
+                let caught_up = catch_up.run();
+                tokio::pin!(caught_up);
+
+                let ledger = loop {
+                    let mut running = handles.borrow().clone();
+                    tokio::select! {
+                        ledger = &mut caught_up => break ledger,
+                        (delivered, _, _) = future::select_all(
+                            running.iter_mut().map(|handle| Box::pin(handle.deliver())),
+                        ) => match delivered {
+                            Err(contagion::ContagionError::Channel) => return Ok(()),
+                            Err(err) => warn!("deliver batch: {}", err),
+                            Ok(batch) => {
+                                if let Some(journal) = journal.as_mut() {
+                                    journal.append(batch.iter()).context(JournalAppend)?;
+                                }
+
+                                buffered.extend(batch.iter().cloned());
+                            }
+                        },
+                    }
+                };
+
+                match ledger {
+                    Some(ledger) => {
+                        if let Err(err) = service.restore(ledger, &mut pending).await {
+                            warn!("unable to restore ledger: {}", err);
+                        }
+                    }
+                    None => warn!("unable to catch up in time, going on with the local state"),
+                }
+
+                service.queue_unapplied(&buffered, &mut pending).await?;
+                service.process_ready(&mut pending).await;
+            }
+            let _ = synced.send(true);
 
             loop {
-                match service.handle.deliver().await {
-                    Err(contagion::ContagionError::Channel) => break,
+                // the applied payloads are already stored with the accounts
+                if let Some(journal) = journal.as_mut().filter(|journal| {
+                    journal.records() >= pending.depth() + JOURNAL_COMPACTION_SIZE
+                }) {
+                    journal
+                        .compact(pending.payloads())
+                        .context(JournalCompact)?;
+                }
+
+                let _ = pending_payloads.send(pending.payloads().cloned().collect());
+                let _ = pending_depths.send(pending.depths());
+
+                let mut running = handles.borrow().clone();
+                if running.is_empty() {
+                    break Ok(());
+                }
+                let delivered = tokio::select! {
+                    (delivered, _, _) = future::select_all(
+                        running.iter_mut().map(|handle| Box::pin(handle.deliver())),
+                    ) => delivered,
+                    // restarted, also delivering from the new broadcast
+                    changed = handles.changed() => match changed {
+                        Ok(()) => continue,
+                        Err(_) => break Ok(()),
+                    },
+                };
+
+                match delivered {
+                    Err(contagion::ContagionError::Channel) => break Ok(()),
                     Err(err) => {
                         warn!("deliver batch: {}", err);
                         continue;
                     }
                     Ok(batch) => {
-                        batch.iter().for_each(|msg| {
-                            to_process.push(Reverse((
-                                (
-                                    msg.sequence(),
-                                    msg.sender().to_owned(),
-                                    msg.payload().to_owned(),
-                                ),
-                                Instant::now(),
-                            )))
-                        });
+                        // not applied if unable to replay it after a crash
+                        if let Some(journal) = journal.as_mut() {
+                            journal.append(batch.iter()).context(JournalAppend)?;
+                        }
+
+                        batch.iter().for_each(|msg| pending.push(msg));
                     }
                 };
 
-                // dirty way to process all transactions
-                let mut previous_len = usize::MAX;
-                while to_process.len() < previous_len {
-                    previous_len = to_process.len();
-
-                    let mut remaining_to_process = BinaryHeap::with_capacity(to_process.len());
-                    for Reverse((msg, when_added)) in to_process.into_sorted_vec() {
-                        if when_added.elapsed() > TRANSACTION_TTL {
-                            warn!("dropping too old: {:?}", msg);
-
-                            if let Err(err) = service
-                                .recent_transactions
-                                .update(Box::new(msg.1), msg.0, TransactionState::Failure)
-                                .await
-                            {
-                                warn!("unable to process: {}", err);
-                            }
-                        }
+                service.process_ready(&mut pending).await;
+            }
+        })
+    }
 
-                        if let Err(err) = service.process_payload(msg.clone()).await {
-                            // retry only account async failures
-                            if let ProcessTransactionError::ProcessTxForAccounts {
-                                source: accounts::Error::AccountModification { .. },
-                            } = err
-                            {
-                                remaining_to_process.push(Reverse((msg, when_added)));
-                            } else {
-                                warn!("unable to process: {}", err);
-                            }
+    /// Replace the accounts by the ones agreed on by the network and queue its pending payloads
+    async fn restore(
+        &mut self,
+        ledger: sync::Ledger,
+        pending: &mut Pending,
+    ) -> Result<(), accounts::Error> {
+        self.accounts.restore(ledger.accounts).await?;
+
+        ledger.pending.iter().for_each(|msg| pending.push(msg));
+        self.process_ready(pending).await;
+
+        Ok(())
+    }
+
+    /// Apply the pending transactions of the ready senders, in sequence
+    ///
+    /// A sender stops at its first transaction unable to be applied yet, retried once it is
+    /// ready again.
+    async fn process_ready(&mut self, pending: &mut Pending) {
+        for msg in pending.take_expired(TRANSACTION_TTL) {
+            warn!("dropping too old: {:?}", msg);
+
+            self.mark_as_failed(&msg, FailureReason::Expired).await;
+        }
+
+        while let Some(sender) = pending.next_ready() {
+            while let Some(msg) = pending.first(&sender) {
+                match self.process_payload(msg.clone()).await {
+                    Ok(()) => {
+                        pending.pop_first(&sender);
+
+                        if let Operation::CreateAccount = msg.2 {
+                            pending.account_created();
+                        }
+                    }
+                    // retry only account async failures
+                    Err(err) if err.is_retryable() => {
+                        if err.is_missing_account() {
+                            pending.wait_for_account(sender);
                         }
+                        break;
+                    }
+                    Err(err) => {
+                        warn!("unable to process: {}", err);
+
+                        self.mark_as_failed(&msg, err.failure_reason()).await;
+                        pending.pop_first(&sender);
                     }
-                    to_process = remaining_to_process;
                 }
             }
-        });
+        }
+    }
+
+    /// Resolve an applied transaction, adding its outputs to the history and the committed
+    ///
+    /// The outputs are the delivered ones, the recent transactions only knowing the ones seen
+    /// lately. As the accounts are already changed, it succeeds even if unable to record it.
+    async fn commit(
+        &self,
+        sender: Box<sign::PublicKey>,
+        sequence: sieve::Sequence,
+        outputs: Vec<ThinTransaction>,
+    ) {
+        if let Err(err) = self
+            .recent_transactions
+            .update(sender.clone(), sequence, TransactionState::Success)
+            .await
+        {
+            warn!("unable to resolve: {}", err);
+        }
+
+        let timestamp = chrono::Utc::now();
+        for thin in outputs {
+            let tx = FullTransaction {
+                timestamp,
+                sender: *sender,
+                sender_sequence: sequence,
+                recipient: thin.recipient,
+                asset: thin.asset,
+                amount: thin.amount,
+                state: TransactionState::Success,
+                memo: thin.memo,
+            };
+
+            if let Err(err) = self.history.record(tx.clone()).await {
+                warn!(?sender, sequence, "unable to record in history: {}", err);
+            }
+
+            // no subscriber is not an error
+            let _ = self.committed.send(tx);
+        }
+    }
+
+    async fn mark_as_failed(
+        &self,
+        (sequence, sender, _): &pending::Transaction,
+        reason: FailureReason,
+    ) {
+        if let Err(err) = self
+            .recent_transactions
+            .update(
+                Box::new(*sender),
+                *sequence,
+                TransactionState::Failure(reason),
+            )
+            .await
+        {
+            warn!("unable to process: {}", err);
+        }
     }
 
     async fn process_payload(
         &mut self,
-        (sequence, sender, payload): (sieve::Sequence, sign::PublicKey, ThinTransaction),
+        (sequence, sender, payload): pending::Transaction,
     ) -> Result<(), ProcessTransactionError> {
         info!(sequence, ?sender, tx=?payload, "new payload");
 
         let sender = Box::new(sender);
 
-        self.accounts
-            .transfer(
-                sender.clone(),
-                sequence,
-                Box::new(payload.recipient),
-                payload.amount,
-            )
-            .await
-            .context(ProcessTxForAccounts)?;
+        match payload {
+            Operation::CreateAccount => {
+                ensure!(
+                    sequence == CREATE_ACCOUNT_SEQUENCE,
+                    CreateAccountSequence { sequence }
+                );
+
+                self.accounts
+                    .create(sender)
+                    .await
+                    .context(ProcessTxForAccounts)?;
+            }
+            Operation::Transfer(thin) => {
+                self.recent_transactions
+                    .put(sender.clone(), sequence, vec![thin.clone()])
+                    .await
+                    .context(ProcessTxForRecent)?;
+
+                ensure!(thin.has_valid_memo(), MemoSize);
+
+                self.accounts
+                    .transfer(
+                        sender.clone(),
+                        sequence,
+                        Box::new(thin.recipient),
+                        thin.asset.clone(),
+                        thin.amount,
+                    )
+                    .await
+                    .context(ProcessTxForAccounts)?;
+
+                self.commit(sender, sequence, vec![thin]).await;
+            }
+            Operation::Batch(outputs) => {
+                ensure!(
+                    (1..=BATCH_MAX_SIZE).contains(&outputs.len()),
+                    BatchSize {
+                        size: outputs.len()
+                    }
+                );
+
+                self.recent_transactions
+                    .put(sender.clone(), sequence, outputs.clone())
+                    .await
+                    .context(ProcessTxForRecent)?;
+
+                ensure!(
+                    outputs.iter().all(ThinTransaction::has_valid_memo),
+                    MemoSize
+                );
+
+                self.accounts
+                    .transfer_batch(
+                        sender.clone(),
+                        sequence,
+                        outputs
+                            .iter()
+                            .map(|thin| (thin.recipient, thin.asset.clone(), thin.amount))
+                            .collect(),
+                    )
+                    .await
+                    .context(ProcessTxForAccounts)?;
+
+                self.commit(sender, sequence, outputs).await;
+            }
+            Operation::Issue {
+                asset,
+                amount,
+                sequence: signed,
+            } => {
+                ensure!(signed == sequence, SignedSequence { signed, sequence });
+                ensure!(
+                    self.authorities.issuer.as_ref() == Some(&*sender),
+                    NotIssuer
+                );
+                ensure!(is_issuable_asset(&asset), InvalidAsset { asset });
+
+                self.accounts
+                    .issue(sender, sequence, asset, amount)
+                    .await
+                    .context(ProcessTxForAccounts)?;
+            }
+            Operation::Membership {
+                change,
+                sequence: signed,
+            } => {
+                ensure!(signed == sequence, SignedSequence { signed, sequence });
+                ensure!(self.authorities.admin.as_ref() == Some(&*sender), NotAdmin);
+
+                self.accounts
+                    .use_sequence(sender, sequence)
+                    .await
+                    .context(ProcessTxForAccounts)?;
+
+                if self
+                    .peers
+                    .apply(sequence, change)
+                    .await
+                    .context(ProcessTxForPeers)?
+                {
+                    let members = self.peers.members().await.context(ProcessTxForPeers)?;
+                    // already applied, the broadcast is only left among the previous nodes
+                    if let Err(err) = self.network.restart(&members).await {
+                        warn!("unable to follow the membership change: {}", err);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reject an operation known to fail once agreed on by the network
+    ///
+    /// It is checked against the current state, once caught up with the network, so it can
+    /// still fail because of the transactions not yet processed.
+    async fn validate(
+        &self,
+        sender: &sign::PublicKey,
+        sequence: sieve::Sequence,
+        operation: &Operation,
+        signature: &sign::Signature,
+    ) -> Result<(), tonic::Status> {
+        self.wait_for_sync().await?;
+
+        signature
+            .verify(operation, sender)
+            .map_err(|_| tonic::Status::unauthenticated("invalid signature"))?;
+
+        match operation {
+            Operation::CreateAccount => {
+                return match self.accounts.get_last_sequence(Box::new(*sender)).await {
+                    Err(accounts::Error::NoSuchAccount { .. }) => Ok(()),
+                    Err(err) => Err(err.into()),
+                    Ok(_) => Err(tonic::Status::already_exists("account already exists")),
+                };
+            }
+            Operation::Transfer(_) => {}
+            Operation::Batch(outputs) => {
+                if !(1..=BATCH_MAX_SIZE).contains(&outputs.len()) {
+                    return Err(tonic::Status::invalid_argument(format!(
+                        "batch should have between 1 and {} outputs",
+                        BATCH_MAX_SIZE
+                    )));
+                }
+            }
+            Operation::Issue {
+                asset,
+                sequence: signed,
+                ..
+            } => {
+                ensure_signed_sequence(*signed, sequence)?;
+                if self.authorities.issuer != Some(*sender) {
+                    return Err(tonic::Status::permission_denied("not the issuer"));
+                }
+                if !is_issuable_asset(asset) {
+                    return Err(tonic::Status::invalid_argument(format!(
+                        "unissuable asset: {}",
+                        asset
+                    )));
+                }
+            }
+            Operation::Membership {
+                sequence: signed, ..
+            } => {
+                ensure_signed_sequence(*signed, sequence)?;
+                if self.authorities.admin != Some(*sender) {
+                    return Err(tonic::Status::permission_denied("not the admin"));
+                }
+            }
+        }
+
+        let last_sequence = self.accounts.get_last_sequence(Box::new(*sender)).await?;
+        if sequence <= last_sequence {
+            return Err(tonic::Status::already_exists(format!(
+                "sequence {} already used, last is {}",
+                sequence, last_sequence
+            )));
+        }
+
+        match operation {
+            Operation::Transfer(thin) => {
+                self.validate_outputs(sender, std::slice::from_ref(thin))
+                    .await
+            }
+            Operation::Batch(outputs) => self.validate_outputs(sender, outputs).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject outputs with an unknown recipient or not covered by the sender's balances
+    async fn validate_outputs(
+        &self,
+        sender: &sign::PublicKey,
+        outputs: &[ThinTransaction],
+    ) -> Result<(), tonic::Status> {
+        outputs.iter().try_for_each(ensure_valid_memo)?;
+
+        for thin in outputs {
+            match self
+                .accounts
+                .get_last_sequence(Box::new(thin.recipient))
+                .await
+            {
+                Ok(_) => {}
+                Err(accounts::Error::NoSuchAccount { .. }) => {
+                    return Err(tonic::Status::not_found(format!(
+                        "no such recipient: {}",
+                        thin.recipient
+                    )))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let balances = self.accounts.get_balances(Box::new(*sender)).await?;
+
+        let fee = self
+            .accounts
+            .fee()
+            .map(|fee| fee.amount)
+            .unwrap_or_default();
+        let mut totals = BTreeMap::<&str, u64>::new();
+        totals.insert(NATIVE_ASSET, fee);
+        // transfers to itself are not moving any asset
+        for thin in outputs.iter().filter(|thin| thin.recipient != *sender) {
+            let total = totals.entry(&thin.asset).or_default();
+            *total = total.saturating_add(thin.amount);
+        }
+
+        for (asset, total) in totals {
+            if total > balances.get(asset).copied().unwrap_or_default() {
+                return Err(tonic::Status::failed_precondition(format!(
+                    "insufficient funds to send {} {} with a fee of {}",
+                    total - if asset == NATIVE_ASSET { fee } else { 0 },
+                    asset,
+                    fee
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Proof that the user's account is part of the state root signed by this node
+    async fn account_proof(&self, user: &sign::PublicKey) -> Result<AccountProof, tonic::Status> {
+        use account_proof::{sibling::Side, Sibling};
+
+        let proven = self.accounts.proven().await?;
+        let (account, proof, state_root) =
+            proven
+                .prove(user)
+                .ok_or_else(|| accounts::Error::NoSuchAccount {
+                    pubkey: Box::new(*user),
+                })?;
+
+        let signature = state::sign_state_root(&self.keypair, &state_root)
+            .map_err(|err| tonic::Status::internal(format!("sign state root: {}", err)))?;
+
+        Ok(AccountProof {
+            last_sequence: account.last_sequence(),
+            balances: account.balances().clone().into_iter().collect(),
+            siblings: proof
+                .siblings
+                .into_iter()
+                .map(|sibling| Sibling {
+                    side: Some(match sibling {
+                        state::Sibling::Left(left) => Side::Left(left.to_vec()),
+                        state::Sibling::Right(right) => Side::Right(right.to_vec()),
+                    }),
+                })
+                .collect(),
+            state_root: state_root.to_vec(),
+            node: bincode::serialize(&self.keypair.public()).context(Serialize)?,
+            signature: bincode::serialize(&signature).context(Serialize)?,
+        })
+    }
+
+    /// Sign the reply to the request with the given nonce, for the client to prove what this
+    /// node told
+    fn sign_reply(&self, reply: &SignedReply, nonce: u64) -> Result<NodeSignature, tonic::Status> {
+        let signature = reply
+            .sign(&self.keypair, nonce)
+            .map_err(|err| tonic::Status::internal(format!("sign reply: {}", err)))?;
+
+        Ok(NodeSignature {
+            node: bincode::serialize(&self.keypair.public()).context(Serialize)?,
+            signature: bincode::serialize(&signature).context(Serialize)?,
+        })
+    }
+
+    /// Wait for the node to catch up with the network, as its state is outdated until then
+    async fn wait_for_sync(&self) -> Result<(), tonic::Status> {
+        let mut synced = self.synced.clone();
+
+        tokio::time::timeout(CATCH_UP_WAIT_TIMEOUT, async move {
+            while !*synced.borrow() {
+                if synced.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+        .await
+        .map_err(|_| tonic::Status::unavailable("catching up with the network"))
+    }
+
+    /// Track the operation if transferring, then broadcast it to the network
+    async fn broadcast(
+        &self,
+        sender: sign::PublicKey,
+        sequence: sieve::Sequence,
+        operation: Operation,
+        signature: sign::Signature,
+    ) -> Result<(), tonic::Status> {
+        let mut handle = self
+            .network
+            .handle()
+            .ok_or_else(|| tonic::Status::unavailable("not taking part in the broadcast"))?;
+
+        let outputs = match &operation {
+            Operation::Transfer(thin) => Some(vec![thin.clone()]),
+            Operation::Batch(outputs) => Some(outputs.clone()),
+            _ => None,
+        };
+        if let Some(outputs) = outputs {
+            self.recent_transactions
+                .put(Box::new(sender), sequence, outputs)
+                .await?;
+        }
 
-        self.recent_transactions
-            .update(sender, sequence, TransactionState::Success)
+        handle
+            .broadcast(&sieve::Payload::new(sender, sequence, operation, signature))
             .await
-            .context(ProcessTxForRecent)?;
+            .map_err(|err| tonic::Status::invalid_argument(err.to_string()))
+    }
+}
+
+fn full_transaction_to_proto(tx: &FullTransaction) -> Result<proto::FullTransaction, ProtoError> {
+    use full_transaction::{FailureReason as Reason, State};
+
+    Ok(proto::FullTransaction {
+        timestamp: tx.timestamp.to_rfc3339(),
+        sender: bincode::serialize(&tx.sender).context(Serialize)?,
+        sender_sequence: tx.sender_sequence,
+        recipient: bincode::serialize(&tx.recipient).context(Serialize)?,
+        asset: tx.asset.clone(),
+        amount: tx.amount,
+        state: match tx.state {
+            TransactionState::Pending => State::Pending as i32,
+            TransactionState::Success => State::Success as i32,
+            TransactionState::Failure(_) => State::Failure as i32,
+        },
+        failure_reason: match tx.state {
+            TransactionState::Failure(FailureReason::InsufficientFunds) => {
+                Reason::InsufficientFunds as i32
+            }
+            TransactionState::Failure(FailureReason::Overflow) => Reason::Overflow as i32,
+            TransactionState::Failure(FailureReason::SequenceGap) => Reason::SequenceGap as i32,
+            TransactionState::Failure(FailureReason::Expired) => Reason::Expired as i32,
+            TransactionState::Failure(FailureReason::NoSuchAccount) => Reason::NoSuchAccount as i32,
+            _ => Reason::Other as i32,
+        },
+        memo: tx.memo.clone().unwrap_or_default(),
+    })
+}
+
+fn asset_from_proto(asset: String) -> String {
+    if asset.is_empty() {
+        NATIVE_ASSET.to_owned()
+    } else {
+        asset
+    }
+}
+
+fn memo_from_proto(memo: String) -> Option<String> {
+    Some(memo).filter(|memo| !memo.is_empty())
+}
+
+/// Transfer requested, as its sender, sequence, operation and signature
+fn transfer_from_proto(
+    message: SendAssetRequest,
+) -> Result<(sign::PublicKey, sieve::Sequence, Operation, sign::Signature), ProtoError> {
+    Ok((
+        bincode::deserialize(&message.sender).context(Deserialize)?,
+        message.sequence,
+        Operation::Transfer(ThinTransaction {
+            recipient: bincode::deserialize(&message.recipient).context(Deserialize)?,
+            asset: asset_from_proto(message.asset),
+            amount: message.amount,
+            memo: memo_from_proto(message.memo),
+        }),
+        bincode::deserialize(&message.signature).context(Deserialize)?,
+    ))
+}
+
+fn ensure_valid_memo(thin: &ThinTransaction) -> Result<(), tonic::Status> {
+    if thin.has_valid_memo() {
+        Ok(())
+    } else {
+        Err(tonic::Status::invalid_argument(format!(
+            "memo longer than {} bytes",
+            MEMO_MAX_SIZE
+        )))
+    }
+}
 
+/// Reject an operation signed for another sequence than the one it is sent with
+fn ensure_signed_sequence(
+    signed: sieve::Sequence,
+    sequence: sieve::Sequence,
+) -> Result<(), tonic::Status> {
+    if signed == sequence {
         Ok(())
+    } else {
+        Err(tonic::Status::invalid_argument(format!(
+            "signed for sequence {} but sent with {}",
+            signed, sequence
+        )))
     }
 }
 
@@ -244,7 +971,11 @@ impl From<ProtoError> for tonic::Status {
 }
 impl From<accounts::Error> for tonic::Status {
     fn from(err: accounts::Error) -> Self {
-        Self::invalid_argument(err.to_string())
+        match err {
+            accounts::Error::NoSuchAccount { .. } => Self::not_found(err.to_string()),
+            accounts::Error::HashAccounts { .. } => Self::internal(err.to_string()),
+            _ => Self::invalid_argument(err.to_string()),
+        }
     }
 }
 impl From<recent_transactions::Error> for tonic::Status {
@@ -252,63 +983,233 @@ impl From<recent_transactions::Error> for tonic::Status {
         Self::invalid_argument(err.to_string())
     }
 }
+impl From<history::Error> for tonic::Status {
+    fn from(err: history::Error) -> Self {
+        Self::invalid_argument(err.to_string())
+    }
+}
+impl From<snapshot::Error> for tonic::Status {
+    fn from(err: snapshot::Error) -> Self {
+        Self::internal(err.to_string())
+    }
+}
+impl From<peers::Error> for tonic::Status {
+    fn from(err: peers::Error) -> Self {
+        Self::internal(err.to_string())
+    }
+}
 
 #[tonic::async_trait]
 impl at2_server::At2 for Service {
+    type WatchTransactionStream =
+        Pin<Box<dyn Stream<Item = Result<WatchTransactionReply, tonic::Status>> + Send>>;
+    type SubscribeTransactionsStream =
+        Pin<Box<dyn Stream<Item = Result<SubscribeTransactionsReply, tonic::Status>> + Send>>;
+
+    async fn create_account(
+        &self,
+        request: tonic::Request<CreateAccountRequest>,
+    ) -> Result<tonic::Response<CreateAccountReply>, tonic::Status> {
+        let message = request.into_inner();
+
+        let sender = bincode::deserialize(&message.sender).context(Deserialize)?;
+        let signature = bincode::deserialize(&message.signature).context(Deserialize)?;
+
+        self.validate(
+            &sender,
+            CREATE_ACCOUNT_SEQUENCE,
+            &Operation::CreateAccount,
+            &signature,
+        )
+        .await?;
+        self.broadcast(
+            sender,
+            CREATE_ACCOUNT_SEQUENCE,
+            Operation::CreateAccount,
+            signature,
+        )
+        .await?;
+
+        Ok(Response::new(CreateAccountReply {}))
+    }
+
     async fn send_asset(
         &self,
         request: tonic::Request<SendAssetRequest>,
     ) -> Result<tonic::Response<SendAssetReply>, tonic::Status> {
+        let (sender, sequence, operation, signature) = transfer_from_proto(request.into_inner())?;
+
+        self.validate(&sender, sequence, &operation, &signature)
+            .await?;
+        self.broadcast(sender, sequence, operation, signature)
+            .await?;
+
+        Ok(Response::new(SendAssetReply {}))
+    }
+
+    async fn send_batch(
+        &self,
+        request: tonic::Request<SendBatchRequest>,
+    ) -> Result<tonic::Response<SendBatchReply>, tonic::Status> {
         let message = request.into_inner();
 
-        let thin = at2_node::ThinTransaction {
-            recipient: bincode::deserialize(&message.recipient).context(Deserialize)?,
+        let outputs = message
+            .outputs
+            .into_iter()
+            .map(|output| {
+                Ok(ThinTransaction {
+                    recipient: bincode::deserialize(&output.recipient).context(Deserialize)?,
+                    asset: asset_from_proto(output.asset),
+                    amount: output.amount,
+                    memo: memo_from_proto(output.memo),
+                })
+            })
+            .collect::<Result<Vec<_>, ProtoError>>()?;
+
+        let sender = bincode::deserialize(&message.sender).context(Deserialize)?;
+        let operation = Operation::Batch(outputs);
+        let signature = bincode::deserialize(&message.signature).context(Deserialize)?;
+
+        self.validate(&sender, message.sequence, &operation, &signature)
+            .await?;
+        self.broadcast(sender, message.sequence, operation, signature)
+            .await?;
+
+        Ok(Response::new(SendBatchReply {}))
+    }
+
+    async fn issue_asset(
+        &self,
+        request: tonic::Request<IssueAssetRequest>,
+    ) -> Result<tonic::Response<IssueAssetReply>, tonic::Status> {
+        let message = request.into_inner();
+
+        let sender = bincode::deserialize(&message.sender).context(Deserialize)?;
+        let operation = Operation::Issue {
+            asset: message.asset,
             amount: message.amount,
+            sequence: message.sequence,
         };
+        let signature = bincode::deserialize(&message.signature).context(Deserialize)?;
 
-        let sender = bincode::deserialize(&message.sender).context(Deserialize)?;
+        self.validate(&sender, message.sequence, &operation, &signature)
+            .await?;
+        self.broadcast(sender, message.sequence, operation, signature)
+            .await?;
+
+        Ok(Response::new(IssueAssetReply {}))
+    }
+
+    async fn send_asset_and_wait(
+        &self,
+        request: tonic::Request<SendAssetRequest>,
+    ) -> Result<tonic::Response<SendAssetAndWaitReply>, tonic::Status> {
+        let (sender, sequence, operation, signature) = transfer_from_proto(request.into_inner())?;
 
-        self.recent_transactions
-            .put(Box::new(sender), message.sequence, thin.clone())
+        self.validate(&sender, sequence, &operation, &signature)
             .await?;
 
-        self.handle
-            .clone()
-            .broadcast(&sieve::Payload::new(
-                sender,
-                message.sequence,
-                thin,
-                bincode::deserialize(&message.signature).context(Deserialize)?,
-            ))
-            .await
-            .map_err(|err| tonic::Status::invalid_argument(err.to_string()))?;
+        // watch before broadcasting to not miss any state
+        let mut watcher = self
+            .recent_transactions
+            .watch(Box::new(sender), sequence)
+            .await?;
 
-        Ok(Response::new(SendAssetReply {}))
+        self.broadcast(sender, sequence, operation, signature)
+            .await?;
+
+        let transaction = tokio::time::timeout(TRANSACTION_WAIT_TIMEOUT, async move {
+            let mut last = None;
+            while let Some(tx) = watcher.recv().await {
+                last = Some(tx);
+            }
+            last
+        })
+        .await
+        .map_err(|_| tonic::Status::deadline_exceeded("transaction not resolved in time"))?
+        .ok_or_else(|| tonic::Status::internal("transaction not tracked"))?;
+
+        Ok(Response::new(SendAssetAndWaitReply {
+            transaction: Some(full_transaction_to_proto(&transaction)?),
+        }))
     }
 
     async fn get_last_sequence(
         &self,
         request: tonic::Request<GetLastSequenceRequest>,
     ) -> Result<tonic::Response<GetLastSequenceReply>, tonic::Status> {
-        let sequence = self
-            .accounts
-            .get_last_sequence(
-                bincode::deserialize(&request.get_ref().sender).context(Deserialize)?,
-            )
-            .await?;
+        let user: Box<sign::PublicKey> =
+            bincode::deserialize(&request.get_ref().sender).context(Deserialize)?;
+
+        let sequence = self.accounts.get_last_sequence(user.clone()).await?;
 
-        Ok(Response::new(GetLastSequenceReply { sequence }))
+        Ok(Response::new(GetLastSequenceReply {
+            sequence,
+            signature: Some(self.sign_reply(
+                &SignedReply::LastSequence {
+                    user: *user,
+                    sequence,
+                },
+                request.get_ref().nonce,
+            )?),
+        }))
     }
 
     async fn get_balance(
         &self,
         request: tonic::Request<GetBalanceRequest>,
     ) -> Result<tonic::Response<GetBalanceReply>, tonic::Status> {
-        Ok(Response::new(GetBalanceReply {
-            amount: self
+        self.wait_for_sync().await?;
+
+        let message = request.into_inner();
+        let user: Box<sign::PublicKey> =
+            bincode::deserialize(&message.sender).context(Deserialize)?;
+        let asset = asset_from_proto(message.asset);
+
+        // read the balances from the proof, to be sure they match it
+        let (balances, proof) = if message.prove {
+            let proof = self.account_proof(&user).await?;
+            (proof.balances.clone().into_iter().collect(), Some(proof))
+        } else if message.portfolio {
+            (self.accounts.get_balances(user.clone()).await?, None)
+        } else {
+            let amount = self
                 .accounts
-                .get_balance(bincode::deserialize(&request.get_ref().sender).context(Deserialize)?)
-                .await?,
+                .get_balance(user.clone(), asset.clone())
+                .await?;
+            (BTreeMap::from([(asset.clone(), amount)]), None)
+        };
+
+        let (amount, balances) = if message.portfolio {
+            (0, balances)
+        } else {
+            (
+                balances.get(&asset).copied().unwrap_or_default(),
+                BTreeMap::new(),
+            )
+        };
+
+        let signature = self.sign_reply(
+            &if message.portfolio {
+                SignedReply::Portfolio {
+                    user: *user,
+                    balances: balances.clone(),
+                }
+            } else {
+                SignedReply::Balance {
+                    user: *user,
+                    asset,
+                    amount,
+                }
+            },
+            message.nonce,
+        )?;
+
+        Ok(Response::new(GetBalanceReply {
+            amount,
+            balances: balances.into_iter().collect(),
+            proof,
+            signature: Some(signature),
         }))
     }
 
@@ -316,29 +1217,286 @@ impl at2_server::At2 for Service {
         &self,
         _: tonic::Request<GetLatestTransactionsRequest>,
     ) -> Result<tonic::Response<GetLatestTransactionsReply>, tonic::Status> {
-        use full_transaction::State;
-
         Ok(Response::new(GetLatestTransactionsReply {
             transactions: self
                 .recent_transactions
                 .get_all()
                 .await?
                 .iter()
-                .map(|tx| {
-                    Ok(proto::FullTransaction {
-                        timestamp: tx.timestamp.to_rfc3339(),
-                        sender: bincode::serialize(&tx.sender).context(Serialize)?,
-                        sender_sequence: tx.sender_sequence,
-                        recipient: bincode::serialize(&tx.recipient).context(Serialize)?,
-                        amount: tx.amount,
-                        state: match tx.state {
-                            TransactionState::Pending => State::Pending as i32,
-                            TransactionState::Success => State::Success as i32,
-                            TransactionState::Failure => State::Failure as i32,
-                        },
+                .map(full_transaction_to_proto)
+                .collect::<Result<_, ProtoError>>()?,
+        }))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: tonic::Request<GetTransactionRequest>,
+    ) -> Result<tonic::Response<GetTransactionReply>, tonic::Status> {
+        let message = request.into_inner();
+
+        let outputs = self
+            .recent_transactions
+            .get(
+                bincode::deserialize(&message.sender).context(Deserialize)?,
+                message.sequence,
+            )
+            .await?
+            .ok_or_else(|| tonic::Status::not_found("unknown transaction"))?
+            .iter()
+            .map(full_transaction_to_proto)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Response::new(GetTransactionReply {
+            signature: Some(
+                self.sign_reply(
+                    &SignedReply::Transaction(
+                        outputs
+                            .iter()
+                            .map(|output| output.encode_to_vec())
+                            .collect(),
+                    ),
+                    message.nonce,
+                )?,
+            ),
+            outputs,
+        }))
+    }
+
+    async fn get_account_history(
+        &self,
+        request: tonic::Request<GetAccountHistoryRequest>,
+    ) -> Result<tonic::Response<GetAccountHistoryReply>, tonic::Status> {
+        use get_account_history_request::Direction;
+
+        let message = request.into_inner();
+
+        let direction = match Direction::from_i32(message.direction)
+            .ok_or_else(|| tonic::Status::invalid_argument("unknown direction"))?
+        {
+            Direction::Both => HistoryDirection::Both,
+            Direction::Sent => HistoryDirection::Sent,
+            Direction::Received => HistoryDirection::Received,
+        };
+
+        let page = self
+            .history
+            .get(
+                bincode::deserialize(&message.user).context(Deserialize)?,
+                direction,
+                message.cursor.try_into().unwrap_or(usize::MAX),
+                message.page_size as usize,
+            )
+            .await?;
+
+        Ok(Response::new(GetAccountHistoryReply {
+            transactions: page
+                .transactions
+                .iter()
+                .map(full_transaction_to_proto)
+                .collect::<Result<_, _>>()?,
+            next_cursor: page.next_cursor as u64,
+        }))
+    }
+
+    async fn watch_transaction(
+        &self,
+        request: tonic::Request<WatchTransactionRequest>,
+    ) -> Result<tonic::Response<Self::WatchTransactionStream>, tonic::Status> {
+        let message = request.into_inner();
+
+        let watcher = self
+            .recent_transactions
+            .watch(
+                bincode::deserialize(&message.sender).context(Deserialize)?,
+                message.sequence,
+            )
+            .await?;
+
+        let replies = stream::unfold(watcher, |mut watcher| async move {
+            watcher.recv().await.map(|tx| {
+                let reply = full_transaction_to_proto(&tx)
+                    .map(|transaction| WatchTransactionReply {
+                        transaction: Some(transaction),
                     })
+                    .map_err(tonic::Status::from);
+
+                (reply, watcher)
+            })
+        });
+
+        Ok(Response::new(Box::pin(replies)))
+    }
+
+    async fn subscribe_transactions(
+        &self,
+        request: tonic::Request<SubscribeTransactionsRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeTransactionsStream>, tonic::Status> {
+        let message = request.into_inner();
+
+        let deserialize_filter = |key: &[u8]| -> Result<Option<sign::PublicKey>, ProtoError> {
+            if key.is_empty() {
+                Ok(None)
+            } else {
+                bincode::deserialize(key).context(Deserialize).map(Some)
+            }
+        };
+        let sender = deserialize_filter(&message.sender)?;
+        let recipient = deserialize_filter(&message.recipient)?;
+
+        let replies = stream::unfold(self.committed.subscribe(), |mut committed| async move {
+            loop {
+                match committed.recv().await {
+                    Ok(tx) => return Some((tx, committed)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "subscriber lagging behind");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter(move |tx| {
+            future::ready(
+                sender.iter().all(|sender| *sender == tx.sender)
+                    && recipient.iter().all(|recipient| *recipient == tx.recipient),
+            )
+        })
+        .map(|tx| {
+            full_transaction_to_proto(&tx)
+                .map(|transaction| SubscribeTransactionsReply {
+                    transaction: Some(transaction),
                 })
-                .collect::<Result<_, ProtoError>>()?,
+                .map_err(tonic::Status::from)
+        });
+
+        Ok(Response::new(Box::pin(replies)))
+    }
+
+    async fn get_state_root(
+        &self,
+        _: tonic::Request<GetStateRootRequest>,
+    ) -> Result<tonic::Response<GetStateRootReply>, tonic::Status> {
+        Ok(Response::new(GetStateRootReply {
+            state_root: self.accounts.proven().await?.state_root().to_vec(),
+        }))
+    }
+
+    async fn get_peers(
+        &self,
+        _: tonic::Request<GetPeersRequest>,
+    ) -> Result<tonic::Response<GetPeersReply>, tonic::Status> {
+        let peers = self
+            .peers
+            .get_all()
+            .await?
+            .into_iter()
+            .map(|peer| {
+                Ok(get_peers_reply::Peer {
+                    public_key: bincode::serialize(&peer.public_key).context(Serialize)?,
+                    address: peer.address,
+                    connected: peer.connected,
+                    last_seen: peer
+                        .last_seen
+                        .map(|last_seen| last_seen.to_rfc3339())
+                        .unwrap_or_default(),
+                    reconnect_attempts: peer.reconnect_attempts,
+                })
+            })
+            .collect::<Result<_, ProtoError>>()?;
+
+        Ok(Response::new(GetPeersReply { peers }))
+    }
+
+    async fn get_pending(
+        &self,
+        _: tonic::Request<GetPendingRequest>,
+    ) -> Result<tonic::Response<GetPendingReply>, tonic::Status> {
+        let queues = self
+            .pending_depths
+            .borrow()
+            .iter()
+            .map(|(sender, depth)| {
+                Ok(get_pending_reply::Queue {
+                    sender: bincode::serialize(sender).context(Serialize)?,
+                    depth: *depth as u64,
+                })
+            })
+            .collect::<Result<_, ProtoError>>()?;
+
+        Ok(Response::new(GetPendingReply { queues }))
+    }
+
+    async fn change_membership(
+        &self,
+        request: tonic::Request<ChangeMembershipRequest>,
+    ) -> Result<tonic::Response<ChangeMembershipReply>, tonic::Status> {
+        use change_membership_request::Change;
+
+        let message = request.into_inner();
+
+        let sender = bincode::deserialize(&message.sender).context(Deserialize)?;
+
+        let change = match message.change {
+            Some(Change::Add(node)) => MembershipChange::Add {
+                public_key: bincode::deserialize(&node.public_key).context(Deserialize)?,
+                address: node.address,
+                rpc: node.rpc,
+                sign_key: bincode::deserialize(&node.sign_key).context(Deserialize)?,
+            },
+            Some(Change::Remove(public_key)) => MembershipChange::Remove {
+                public_key: bincode::deserialize(&public_key).context(Deserialize)?,
+            },
+            None => return Err(tonic::Status::invalid_argument("missing change")),
+        };
+
+        let operation = Operation::Membership {
+            change,
+            sequence: message.sequence,
+        };
+        let signature = bincode::deserialize(&message.signature).context(Deserialize)?;
+
+        self.validate(&sender, message.sequence, &operation, &signature)
+            .await?;
+        self.broadcast(sender, message.sequence, operation, signature)
+            .await?;
+
+        Ok(Response::new(ChangeMembershipReply {}))
+    }
+
+    async fn get_snapshot(
+        &self,
+        _: tonic::Request<GetSnapshotRequest>,
+    ) -> Result<tonic::Response<GetSnapshotReply>, tonic::Status> {
+        Ok(Response::new(GetSnapshotReply {
+            snapshot: self.accounts.snapshot().await?.encode()?,
+        }))
+    }
+
+    async fn get_ledger(
+        &self,
+        request: tonic::Request<GetLedgerRequest>,
+    ) -> Result<tonic::Response<GetLedgerReply>, tonic::Status> {
+        // pending first, a payload applied meanwhile is then given twice instead of missing
+        let pending = bincode::serialize(&*self.pending_payloads.borrow()).context(Serialize)?;
+        let proven = self.accounts.proven().await?;
+        let snapshot = proven.snapshot();
+
+        let height = snapshot.height();
+        let state_root = proven.state_root();
+        let signature = self.sign_reply(
+            &SignedReply::Ledger {
+                height,
+                state_root,
+                pending: pending.clone(),
+            },
+            request.get_ref().nonce,
+        )?;
+
+        Ok(Response::new(GetLedgerReply {
+            accounts: snapshot.encode()?,
+            pending,
+            height,
+            state_root: state_root.to_vec(),
+            signature: Some(signature),
         }))
     }
 }
@@ -0,0 +1,368 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+use at2_node::{
+    proto::{at2_client::At2Client, GetLedgerRequest},
+    state, SignedReply,
+};
+use drop::crypto::sign;
+use futures::future;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::time::Instant;
+use tonic::transport::Endpoint;
+use tracing::{info, warn};
+
+use super::{
+    accounts::snapshot::{self, Snapshot},
+    config, journal,
+};
+
+/// Delay between two attempts to gather enough agreeing ledgers
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for a node to give its ledger
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to try catching up before going on with the local state
+const CATCH_UP_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("invalid address {}: {}", address, source))]
+    InvalidAddress {
+        address: String,
+        source: http::uri::InvalidUri,
+    },
+    #[snafu(display("connect to {}: {}", address, source))]
+    Connect {
+        address: String,
+        source: tonic::transport::Error,
+    },
+    #[snafu(display("get ledger: {}", source))]
+    GetLedger { source: tonic::Status },
+    #[snafu(display("get ledger: timed out"))]
+    Timeout,
+    #[snafu(display("no signing key pinned"))]
+    MissingSignKey,
+    #[snafu(display("missing signature"))]
+    MissingSignature,
+    #[snafu(display("signed by {} instead of {}", signer, expected))]
+    UnexpectedSigner {
+        signer: sign::PublicKey,
+        expected: sign::PublicKey,
+    },
+    #[snafu(display("invalid reply signature"))]
+    InvalidReplySignature,
+    #[snafu(display("accounts not matching the signed state root at height {}", height))]
+    MismatchedStateRoot { height: u64 },
+    #[snafu(display("decode: {}", source))]
+    Decode { source: bincode::Error },
+    #[snafu(display("decode accounts: {}", source))]
+    DecodeAccounts { source: snapshot::Error },
+    #[snafu(display("invalid signature for sequence {} of {}", sequence, sender))]
+    InvalidSignature {
+        sender: sign::PublicKey,
+        sequence: sieve::Sequence,
+    },
+}
+
+/// State of the network, as agreed on by enough nodes
+#[derive(Debug)]
+pub struct Ledger {
+    pub accounts: Snapshot,
+    /// Delivered payloads not yet applied, with a valid signature
+    pub pending: Vec<journal::Payload>,
+}
+
+/// Ledger of a single node, checked against the state root it signed
+struct Reply {
+    signer: sign::PublicKey,
+    height: u64,
+    state_root: state::Digest,
+    accounts: Snapshot,
+    pending: Vec<journal::Payload>,
+}
+
+/// Catch up with the network by asking the nodes for their ledger
+pub struct CatchUp {
+    /// Nodes to ask, every one with an RPC address and a signing key
+    pub nodes: Vec<config::Node>,
+    /// How many nodes have to sign the same state root, to not trust the faulty ones
+    pub threshold: usize,
+}
+
+impl CatchUp {
+    /// Fetch the ledger of every node until enough of them sign the same state root
+    ///
+    /// Under load, the nodes can keep on replying with different states, so it gives up after
+    /// a while, returning nothing.
+    pub async fn run(&self) -> Option<Ledger> {
+        let deadline = Instant::now() + CATCH_UP_TIMEOUT;
+
+        loop {
+            let replies = future::join_all(self.nodes.iter().map(|node| async move {
+                tokio::time::timeout(FETCH_TIMEOUT, fetch(node))
+                    .await
+                    .unwrap_or(Err(Error::Timeout))
+                    .map_err(|err| {
+                        warn!(node = %node.address, "unable to catch up: {}", err);
+                    })
+            }))
+            .await;
+
+            match agree(replies.into_iter().flatten(), self.threshold) {
+                Some(ledger) => {
+                    info!(
+                        accounts = ledger.accounts.accounts().len(),
+                        pending = ledger.pending.len(),
+                        "caught up"
+                    );
+                    return Some(ledger);
+                }
+                None => info!(threshold = self.threshold, "not enough agreeing ledgers"),
+            }
+
+            if Instant::now() + RETRY_INTERVAL > deadline {
+                return None;
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+}
+
+async fn fetch(node: &config::Node) -> Result<Reply, Error> {
+    let address = node.rpc.as_deref().unwrap_or_default();
+    // otherwise, a faulty node could sign with many keys, each counted as another node
+    let expected = node.sign_key.context(MissingSignKey)?;
+
+    let channel = Endpoint::from_shared(format!("http://{}", address))
+        .context(InvalidAddress { address })?
+        .connect()
+        .await
+        .context(Connect { address })?;
+
+    let nonce = at2_node::nonce();
+    let reply = At2Client::new(channel)
+        .get_ledger(tonic::Request::new(GetLedgerRequest { nonce }))
+        .await
+        .context(GetLedger)?
+        .into_inner();
+
+    let signature = reply.signature.context(MissingSignature)?;
+    let signer = bincode::deserialize(&signature.node).context(Decode)?;
+    ensure!(signer == expected, UnexpectedSigner { signer, expected });
+
+    let height = reply.height;
+    let state_root = reply
+        .state_root
+        .try_into()
+        .map_err(|_| MismatchedStateRoot { height }.build())?;
+    ensure!(
+        SignedReply::Ledger {
+            height,
+            state_root,
+            pending: reply.pending.clone(),
+        }
+        .verify(
+            &bincode::deserialize(&signature.signature).context(Decode)?,
+            &signer,
+            nonce,
+        ),
+        InvalidReplySignature
+    );
+
+    let accounts = Snapshot::decode(&reply.accounts).context(DecodeAccounts)?;
+    ensure!(
+        accounts.height() == height && accounts.state_root().context(DecodeAccounts)? == state_root,
+        MismatchedStateRoot { height }
+    );
+
+    let pending: Vec<journal::Payload> = bincode::deserialize(&reply.pending).context(Decode)?;
+    for msg in pending.iter() {
+        ensure!(
+            msg.signature().verify(msg.payload(), msg.sender()).is_ok(),
+            InvalidSignature {
+                sender: *msg.sender(),
+                sequence: msg.sequence(),
+            }
+        );
+    }
+
+    Ok(Reply {
+        signer,
+        height,
+        state_root,
+        accounts,
+        pending,
+    })
+}
+
+/// Replies signing the same state root, with each signer counted once
+#[derive(Default)]
+struct Agreeing {
+    signers: BTreeSet<sign::PublicKey>,
+    accounts: Option<Snapshot>,
+    pendings: Vec<Vec<journal::Payload>>,
+}
+
+/// Return the highest ledger signed by at least `threshold` nodes, if any
+///
+/// Only the pending payloads given by at least `threshold` of the agreeing replies are kept, as
+/// a faulty node can make up one, signed by a sender it controls. The ones already applied to
+/// the accounts are dropped.
+fn agree(replies: impl IntoIterator<Item = Reply>, threshold: usize) -> Option<Ledger> {
+    let mut by_state = BTreeMap::<(u64, state::Digest), Agreeing>::new();
+    for reply in replies {
+        let agreeing = by_state
+            .entry((reply.height, reply.state_root))
+            .or_default();
+
+        if agreeing.signers.insert(reply.signer) {
+            agreeing.accounts.get_or_insert(reply.accounts);
+            agreeing.pendings.push(reply.pending);
+        }
+    }
+
+    let (accounts, pendings) = by_state
+        .into_values()
+        .rev()
+        .find(|agreeing| agreeing.signers.len() >= threshold)
+        .and_then(|agreeing| Some((agreeing.accounts?, agreeing.pendings)))?;
+
+    let last_sequences = accounts
+        .accounts()
+        .iter()
+        .map(|(user, account)| (*user, account.last_sequence()))
+        .collect::<HashMap<_, _>>();
+
+    // compared as encoded, each reply counted once
+    let mut reported = BTreeMap::<Vec<u8>, (journal::Payload, usize)>::new();
+    for pending in pendings {
+        let encoded = pending
+            .into_iter()
+            .filter_map(|msg| Some((bincode::serialize(&msg).ok()?, msg)))
+            .collect::<BTreeMap<_, _>>();
+        for (encoded, msg) in encoded {
+            reported.entry(encoded).or_insert((msg, 0)).1 += 1;
+        }
+    }
+
+    let pending = reported
+        .into_values()
+        .filter(|(_, count)| *count >= threshold)
+        .map(|(msg, _)| msg)
+        .filter(|msg| {
+            last_sequences
+                .get(msg.sender())
+                .iter()
+                .all(|last| msg.sequence() > **last)
+        })
+        .map(|msg| ((*msg.sender(), msg.sequence()), msg))
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .collect();
+
+    Some(Ledger { accounts, pending })
+}
+
+#[cfg(test)]
+mod tests {
+    use at2_node::Operation;
+
+    use super::{super::accounts::account::Account, *};
+
+    fn reply(accounts: &Snapshot, pending: &[journal::Payload]) -> Reply {
+        Reply {
+            signer: sign::KeyPair::random().public(),
+            height: accounts.height(),
+            state_root: accounts.state_root().expect("to hash accounts"),
+            accounts: accounts.clone(),
+            pending: pending.to_vec(),
+        }
+    }
+
+    fn create_account(user: &sign::KeyPair) -> journal::Payload {
+        let operation = Operation::CreateAccount;
+        let signature = user.sign(&operation).expect("sign failed");
+
+        journal::Payload::new(user.public(), 0, operation, signature)
+    }
+
+    #[test]
+    fn agreed_ledger_needs_threshold_replies() {
+        let accounts =
+            Snapshot::new([(sign::KeyPair::random().public(), Account::with_balance(10))]);
+        let other = Snapshot::new([(sign::KeyPair::random().public(), Account::with_balance(10))]);
+
+        let ledger = agree(
+            [
+                reply(&accounts, &[]),
+                reply(&other, &[]),
+                reply(&accounts, &[]),
+            ],
+            2,
+        )
+        .expect("to agree on a ledger");
+        assert_eq!(accounts.accounts()[0].0, ledger.accounts.accounts()[0].0);
+
+        assert!(agree([reply(&accounts, &[]), reply(&other, &[])], 2).is_none());
+    }
+
+    #[test]
+    fn agreed_ledger_is_the_highest_signed_by_distinct_nodes() {
+        let user = sign::KeyPair::random().public();
+        let lower = Snapshot::new([(user, Account::with_balance(10))]);
+        let mut account = Account::with_balance(10);
+        account
+            .debit(1, 0, [(at2_node::NATIVE_ASSET, 1)])
+            .expect("to debit account");
+        let higher = Snapshot::new([(user, account)]);
+
+        let ledger = agree(
+            [
+                reply(&lower, &[]),
+                reply(&lower, &[]),
+                reply(&higher, &[]),
+                reply(&higher, &[]),
+            ],
+            2,
+        )
+        .expect("to agree on a ledger");
+        assert_eq!(higher.height(), ledger.accounts.height());
+
+        // the same node replying twice
+        let replied = reply(&higher, &[]);
+        let again = Reply {
+            signer: replied.signer,
+            ..reply(&higher, &[])
+        };
+        let ledger = agree([reply(&lower, &[]), reply(&lower, &[]), replied, again], 2)
+            .expect("to agree on a ledger");
+        assert_eq!(lower.height(), ledger.accounts.height());
+    }
+
+    #[test]
+    fn agreed_ledger_keeps_pending_given_by_threshold_replies() {
+        let created = sign::KeyPair::random();
+        let accounts = Snapshot::new([(created.public(), Account::new())]);
+        let (applied, creating, made_up) = (
+            create_account(&created),
+            create_account(&sign::KeyPair::random()),
+            create_account(&sign::KeyPair::random()),
+        );
+
+        let ledger = agree(
+            [
+                reply(&accounts, &[applied.clone(), creating.clone()]),
+                reply(&accounts, &[applied, creating.clone()]),
+                // given twice by the same reply
+                reply(&accounts, &[made_up.clone(), made_up]),
+            ],
+            2,
+        )
+        .expect("to agree on a ledger");
+
+        assert_eq!(1, ledger.pending.len());
+        assert_eq!(creating.sender(), ledger.pending[0].sender());
+    }
+}
@@ -1,12 +1,17 @@
 //! Client for connecting to an AT2 node
 
-use drop::crypto::sign;
+use std::collections::BTreeMap;
+
+use drop::crypto::{key::exchange, sign};
+use futures::{stream::FuturesUnordered, Future, Stream, StreamExt};
 use http::Uri;
-use snafu::{OptionExt, ResultExt, Snafu};
+use prost::Message as _;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 
 use crate::{
-    proto::{at2_client::At2Client, *},
-    FullTransaction, ThinTransaction, TransactionState,
+    proto::{self, at2_client::At2Client, *},
+    state, FailureReason, FullTransaction, HistoryDirection, MembershipChange, Operation,
+    SignedReply, ThinTransaction, TransactionState, NATIVE_ASSET,
 };
 
 /// Error generated by this client
@@ -24,6 +29,18 @@ pub enum Error {
     },
     /// Deserializing the transaction state
     DeserializeState,
+    /// Server's reply is missing the transaction
+    MissingTransaction,
+    /// Server's reply has a state root of the wrong size
+    InvalidStateRoot,
+    /// Server's reply is missing the proof
+    MissingProof,
+    /// Server's proof doesn't lead to the state root
+    InvalidProof,
+    /// Server's reply is missing the signature of the node
+    MissingSignature,
+    /// Server's reply isn't signed by the expected node
+    InvalidSignature,
     /// Serializing the server's query
     Serialize {
         /// Source of the error
@@ -35,15 +52,80 @@ pub enum Error {
         /// Source of the error
         source: tonic::Status,
     },
+    /// Not enough nodes replied to reach the quorum
+    #[snafu(display("only {} nodes replied, {} needed", replied, quorum))]
+    NotEnoughReplies {
+        /// How many nodes replied
+        replied: usize,
+        /// How many nodes have to agree
+        quorum: usize,
+    },
+    /// A node to ask for a quorum has no pinned signing key
+    MissingNodeKey,
+    /// The quorum can't be reached with the given nodes
+    #[snafu(display("quorum of {} for {} distinct nodes", quorum, nodes))]
+    InvalidQuorum {
+        /// How many nodes have to agree
+        quorum: usize,
+        /// How many distinct nodes are asked
+        nodes: usize,
+    },
+    /// Enough nodes replied but not enough of them agreed
+    #[snafu(display("{} nodes replied but no reply was given by {}", replied, quorum))]
+    Disagreement {
+        /// How many nodes replied
+        replied: usize,
+        /// How many nodes have to agree
+        quorum: usize,
+    },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Part of the transactions of an account
+#[derive(Debug)]
+pub struct HistoryPage {
+    /// Transactions of this page, oldest first
+    pub transactions: Vec<FullTransaction>,
+    /// Cursor to use to get the following page
+    pub next_cursor: u64,
+}
+
+/// Health of the link from the node to one of its peers
+#[derive(Debug)]
+pub struct Peer {
+    /// Network key of the peer
+    pub public_key: exchange::PublicKey,
+    /// Where the peer is reached
+    pub address: String,
+    /// If the peer was reachable at the last probe
+    pub connected: bool,
+    /// Last time the peer was reached
+    pub last_seen: Option<chrono::DateTime<chrono::Utc>>,
+    /// Failed attempts to reach the peer since last seen
+    pub reconnect_attempts: u32,
+}
+
+/// Account proven to be part of the state root signed by a node
+#[derive(Debug)]
+pub struct ProvenAccount {
+    /// Latest used sequence
+    pub last_sequence: sieve::Sequence,
+    /// Owned amount of every asset
+    pub balances: BTreeMap<String, u64>,
+    /// Root of the node's state, containing the account
+    pub state_root: state::Digest,
+    /// Signing key of the node vouching for the state root
+    pub node: sign::PublicKey,
+}
+
 /// gRPC web client for the node
 #[derive(Clone)]
 pub struct Client(
     #[cfg(target_family = "wasm")] At2Client<grpc_web_client::Client>,
     #[cfg(not(target_family = "wasm"))] At2Client<tonic::transport::Channel>,
+    /// Signing key of the node, checked against its replies if given
+    Option<sign::PublicKey>,
 );
 
 impl Client {
@@ -60,28 +142,116 @@ impl Client {
         #[cfg(not(target_family = "wasm"))]
         let connection = tonic::transport::Channel::builder(uri).connect_lazy();
 
-        Self(At2Client::new(connection))
+        Self(At2Client::new(connection), None)
+    }
+
+    /// Only accept the replies signed by the given node
+    ///
+    /// Checked for the balances, the last sequences and the transactions, which then
+    /// prove what the node told.
+    pub fn with_node_key(mut self, node: sign::PublicKey) -> Self {
+        self.1 = Some(node);
+        self
+    }
+
+    /// Create the account of the given user.
+    ///
+    /// It has to be done once before sending or receiving any asset.
+    pub async fn create_account(&mut self, user: &sign::KeyPair) -> Result<()> {
+        let signature = user.sign(&Operation::CreateAccount).expect("sign failed");
+
+        self.0
+            .create_account(tonic::Request::new(CreateAccountRequest {
+                sender: bincode::serialize(&user.public()).context(Serialize)?,
+                signature: bincode::serialize(&signature).context(Serialize)?,
+            }))
+            .await
+            .context(Rpc)
+            .map(|_| ())
     }
 
     /// Send a given number of asset to the given user.
     ///
-    /// `sequence` is counter used by the sender.
+    /// `sequence` is counter used by the sender, starting after [`crate::CREATE_ACCOUNT_SEQUENCE`].
     /// You should increase it by one for each new transaction you want to send.
+    /// `asset` is the name of what to send, such as [`crate::NATIVE_ASSET`].
+    /// `memo` is kept along the transaction, up to [`crate::MEMO_MAX_SIZE`] bytes.
     pub async fn send_asset(
         &mut self,
         user: &sign::KeyPair,
         sequence: sieve::Sequence,
         recipient: sign::PublicKey,
+        asset: String,
+        amount: u64,
+        memo: Option<String>,
+    ) -> Result<()> {
+        self.0
+            .send_asset(tonic::Request::new(transfer_request(
+                user, sequence, recipient, asset, amount, memo,
+            )?))
+            .await
+            .context(Rpc)
+            .map(|_| ())
+    }
+
+    /// Send many assets at once, with a single sequence
+    ///
+    /// Either every transfer is applied or none, at most [`crate::BATCH_MAX_SIZE`] of them.
+    pub async fn send_batch(
+        &mut self,
+        user: &sign::KeyPair,
+        sequence: sieve::Sequence,
+        outputs: Vec<ThinTransaction>,
+    ) -> Result<()> {
+        let proto_outputs = outputs
+            .iter()
+            .map(|output| {
+                Ok(send_batch_request::Output {
+                    recipient: bincode::serialize(&output.recipient).context(Serialize)?,
+                    asset: output.asset.clone(),
+                    amount: output.amount,
+                    memo: output.memo.clone().unwrap_or_default(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let message = Operation::Batch(outputs);
+        let signature = user.sign(&message).expect("sign failed");
+
+        self.0
+            .send_batch(tonic::Request::new(SendBatchRequest {
+                sender: bincode::serialize(&user.public()).context(Serialize)?,
+                sequence,
+                outputs: proto_outputs,
+                signature: bincode::serialize(&signature).context(Serialize)?,
+            }))
+            .await
+            .context(Rpc)
+            .map(|_| ())
+    }
+
+    /// Create a given number of a new asset in the user's account
+    ///
+    /// Only the issuer configured on the nodes is allowed to do so.
+    pub async fn issue_asset(
+        &mut self,
+        user: &sign::KeyPair,
+        sequence: sieve::Sequence,
+        asset: String,
         amount: u64,
     ) -> Result<()> {
-        let message = ThinTransaction { recipient, amount };
+        let message = Operation::Issue {
+            asset: asset.clone(),
+            amount,
+            sequence,
+        };
         let signature = user.sign(&message).expect("sign failed");
 
         self.0
-            .send_asset(tonic::Request::new(SendAssetRequest {
+            .issue_asset(tonic::Request::new(IssueAssetRequest {
                 sender: bincode::serialize(&user.public()).context(Serialize)?,
                 sequence,
-                recipient: bincode::serialize(&recipient).context(Serialize)?,
+                asset,
                 amount,
                 signature: bincode::serialize(&signature).context(Serialize)?,
             }))
@@ -90,32 +260,158 @@ impl Client {
             .map(|_| ())
     }
 
-    /// Return the balance of the user
-    pub async fn get_balance(&mut self, user: &sign::PublicKey) -> Result<u64> {
+    /// Send a given number of asset to the given user, waiting for it to be processed.
+    ///
+    /// It returns the resolved transaction, its state telling if it failed and why.
+    pub async fn send_asset_and_wait(
+        &mut self,
+        user: &sign::KeyPair,
+        sequence: sieve::Sequence,
+        recipient: sign::PublicKey,
+        asset: String,
+        amount: u64,
+        memo: Option<String>,
+    ) -> Result<FullTransaction> {
         self.0
+            .send_asset_and_wait(tonic::Request::new(transfer_request(
+                user, sequence, recipient, asset, amount, memo,
+            )?))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .transaction
+            .as_ref()
+            .context(MissingTransaction)
+            .and_then(full_transaction_from_proto)
+    }
+
+    /// Return the balance of the user, in [`crate::NATIVE_ASSET`]
+    pub async fn get_balance(&mut self, user: &sign::PublicKey) -> Result<u64> {
+        self.get_asset_balance(user, NATIVE_ASSET.to_owned()).await
+    }
+
+    /// Return the balance of the user in the given asset
+    pub async fn get_asset_balance(
+        &mut self,
+        user: &sign::PublicKey,
+        asset: String,
+    ) -> Result<u64> {
+        let nonce = crate::nonce();
+        let reply = self
+            .0
             .get_balance(tonic::Request::new(GetBalanceRequest {
                 sender: bincode::serialize(user).context(Serialize)?,
+                asset: asset.clone(),
+                portfolio: false,
+                prove: false,
+                nonce,
             }))
             .await
-            .context(Rpc)
-            .map(|reply| reply.get_ref().amount)
+            .context(Rpc)?
+            .into_inner();
+
+        self.verify_reply(
+            &SignedReply::Balance {
+                user: *user,
+                asset: if asset.is_empty() {
+                    NATIVE_ASSET.to_owned()
+                } else {
+                    asset
+                },
+                amount: reply.amount,
+            },
+            reply.signature,
+            nonce,
+        )?;
+
+        Ok(reply.amount)
+    }
+
+    /// Return the balance of every asset owned by the user
+    pub async fn get_portfolio(&mut self, user: &sign::PublicKey) -> Result<BTreeMap<String, u64>> {
+        let nonce = crate::nonce();
+        let reply = self
+            .0
+            .get_balance(tonic::Request::new(GetBalanceRequest {
+                sender: bincode::serialize(user).context(Serialize)?,
+                asset: String::new(),
+                portfolio: true,
+                prove: false,
+                nonce,
+            }))
+            .await
+            .context(Rpc)?
+            .into_inner();
+
+        let balances: BTreeMap<_, _> = reply.balances.into_iter().collect();
+        self.verify_reply(
+            &SignedReply::Portfolio {
+                user: *user,
+                balances: balances.clone(),
+            },
+            reply.signature,
+            nonce,
+        )?;
+
+        Ok(balances)
+    }
+
+    /// Return the account of the user, verified against the state root signed by the node
+    ///
+    /// The node is only as trusted as its signing key, check [`ProvenAccount::node`] or use
+    /// [`Client::with_node_key`].
+    pub async fn get_proven_account(&mut self, user: &sign::PublicKey) -> Result<ProvenAccount> {
+        let proof = self
+            .0
+            .get_balance(tonic::Request::new(GetBalanceRequest {
+                sender: bincode::serialize(user).context(Serialize)?,
+                asset: String::new(),
+                portfolio: true,
+                prove: true,
+                nonce: crate::nonce(),
+            }))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .proof
+            .context(MissingProof)?;
+
+        let account = verify_account_proof(user, proof)?;
+        ensure!(
+            self.1.iter().all(|node| *node == account.node),
+            InvalidSignature
+        );
+
+        Ok(account)
     }
 
     /// Get the latest used sequence
     pub async fn get_last_sequence(&mut self, user: &sign::PublicKey) -> Result<sieve::Sequence> {
-        self.0
+        let nonce = crate::nonce();
+        let reply = self
+            .0
             .get_last_sequence(tonic::Request::new(GetLastSequenceRequest {
                 sender: bincode::serialize(user).context(Serialize)?,
+                nonce,
             }))
             .await
-            .context(Rpc)
-            .map(|reply| reply.get_ref().sequence)
+            .context(Rpc)?
+            .into_inner();
+
+        self.verify_reply(
+            &SignedReply::LastSequence {
+                user: *user,
+                sequence: reply.sequence,
+            },
+            reply.signature,
+            nonce,
+        )?;
+
+        Ok(reply.sequence)
     }
 
     /// Get the number of recently processed transactions
     pub async fn get_latest_transactions(&mut self) -> Result<Vec<FullTransaction>> {
-        use full_transaction::State;
-
         self.0
             .get_latest_transactions(tonic::Request::new(GetLatestTransactionsRequest {}))
             .await
@@ -123,22 +419,539 @@ impl Client {
             .into_inner()
             .transactions
             .iter()
-            .map(|tx| {
-                Ok(FullTransaction {
-                    timestamp: chrono::DateTime::parse_from_rfc3339(&tx.timestamp)
-                        .context(DeserializeTimestamp)?
-                        .into(),
-                    sender: bincode::deserialize(&tx.sender).context(Deserialize)?,
-                    sender_sequence: tx.sender_sequence,
-                    recipient: bincode::deserialize(&tx.recipient).context(Deserialize)?,
-                    amount: tx.amount,
-                    state: match State::from_i32(tx.state).context(DeserializeState)? {
-                        State::Pending => TransactionState::Pending,
-                        State::Success => TransactionState::Success,
-                        State::Failure => TransactionState::Failure,
+            .map(full_transaction_from_proto)
+            .collect()
+    }
+
+    /// Get the transaction sent by the user with the given sequence
+    ///
+    /// It returns every output of the transaction, a single one unless sent as a batch.
+    pub async fn get_transaction(
+        &mut self,
+        user: &sign::PublicKey,
+        sequence: sieve::Sequence,
+    ) -> Result<Vec<FullTransaction>> {
+        let nonce = crate::nonce();
+        let reply = self
+            .0
+            .get_transaction(tonic::Request::new(GetTransactionRequest {
+                sender: bincode::serialize(user).context(Serialize)?,
+                sequence,
+                nonce,
+            }))
+            .await
+            .context(Rpc)?
+            .into_inner();
+
+        ensure!(!reply.outputs.is_empty(), MissingTransaction);
+        self.verify_reply(
+            &SignedReply::Transaction(
+                reply
+                    .outputs
+                    .iter()
+                    .map(|output| output.encode_to_vec())
+                    .collect(),
+            ),
+            reply.signature,
+            nonce,
+        )?;
+
+        reply
+            .outputs
+            .iter()
+            .map(full_transaction_from_proto)
+            .collect()
+    }
+
+    /// Get a page of the committed transactions of the user
+    ///
+    /// Start with a `cursor` of zero then use the returned one to continue, until an empty page
+    /// is returned. A `page_size` of zero let the server choose it.
+    pub async fn get_account_history(
+        &mut self,
+        user: &sign::PublicKey,
+        direction: HistoryDirection,
+        cursor: u64,
+        page_size: u32,
+    ) -> Result<HistoryPage> {
+        use get_account_history_request::Direction;
+
+        let reply = self
+            .0
+            .get_account_history(tonic::Request::new(GetAccountHistoryRequest {
+                user: bincode::serialize(user).context(Serialize)?,
+                direction: match direction {
+                    HistoryDirection::Both => Direction::Both as i32,
+                    HistoryDirection::Sent => Direction::Sent as i32,
+                    HistoryDirection::Received => Direction::Received as i32,
+                },
+                cursor,
+                page_size,
+            }))
+            .await
+            .context(Rpc)?
+            .into_inner();
+
+        Ok(HistoryPage {
+            transactions: reply
+                .transactions
+                .iter()
+                .map(full_transaction_from_proto)
+                .collect::<Result<_>>()?,
+            next_cursor: reply.next_cursor,
+        })
+    }
+
+    /// Follow the states taken by the transaction sent by the user with the given sequence
+    ///
+    /// The stream ends once the transaction is resolved, either in success or in failure.
+    pub async fn watch_transaction(
+        &mut self,
+        user: &sign::PublicKey,
+        sequence: sieve::Sequence,
+    ) -> Result<impl Stream<Item = Result<FullTransaction>>> {
+        Ok(self
+            .0
+            .watch_transaction(tonic::Request::new(WatchTransactionRequest {
+                sender: bincode::serialize(user).context(Serialize)?,
+                sequence,
+            }))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .map(|reply| {
+                reply
+                    .context(Rpc)?
+                    .transaction
+                    .as_ref()
+                    .context(MissingTransaction)
+                    .and_then(full_transaction_from_proto)
+            }))
+    }
+
+    /// Follow the transactions committed by the node
+    ///
+    /// Only the ones sent by `sender` and received by `recipient` are returned, when given.
+    pub async fn subscribe_transactions(
+        &mut self,
+        sender: Option<&sign::PublicKey>,
+        recipient: Option<&sign::PublicKey>,
+    ) -> Result<impl Stream<Item = Result<FullTransaction>>> {
+        let serialize_filter = |key: Option<&sign::PublicKey>| {
+            key.map_or_else(|| Ok(Vec::new()), bincode::serialize)
+                .context(Serialize)
+        };
+
+        Ok(self
+            .0
+            .subscribe_transactions(tonic::Request::new(SubscribeTransactionsRequest {
+                sender: serialize_filter(sender)?,
+                recipient: serialize_filter(recipient)?,
+            }))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .map(|reply| {
+                reply
+                    .context(Rpc)?
+                    .transaction
+                    .as_ref()
+                    .context(MissingTransaction)
+                    .and_then(full_transaction_from_proto)
+            }))
+    }
+
+    /// Return the root of the node's state, equal on every node agreeing on the accounts
+    pub async fn get_state_root(&mut self) -> Result<state::Digest> {
+        self.0
+            .get_state_root(tonic::Request::new(GetStateRootRequest {}))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .state_root
+            .try_into()
+            .ok()
+            .context(InvalidStateRoot)
+    }
+
+    /// Get the status of the node's peers
+    pub async fn get_peers(&mut self) -> Result<Vec<Peer>> {
+        self.0
+            .get_peers(tonic::Request::new(GetPeersRequest {}))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .peers
+            .iter()
+            .map(|peer| {
+                Ok(Peer {
+                    public_key: bincode::deserialize(&peer.public_key).context(Deserialize)?,
+                    address: peer.address.clone(),
+                    connected: peer.connected,
+                    last_seen: if peer.last_seen.is_empty() {
+                        None
+                    } else {
+                        Some(
+                            chrono::DateTime::parse_from_rfc3339(&peer.last_seen)
+                                .context(DeserializeTimestamp)?
+                                .into(),
+                        )
                     },
+                    reconnect_attempts: peer.reconnect_attempts,
                 })
             })
             .collect()
     }
+
+    /// Get the number of delivered transactions the node has yet to apply, by sender
+    pub async fn get_pending(&mut self) -> Result<Vec<(sign::PublicKey, u64)>> {
+        self.0
+            .get_pending(tonic::Request::new(GetPendingRequest {}))
+            .await
+            .context(Rpc)?
+            .into_inner()
+            .queues
+            .iter()
+            .map(|queue| {
+                Ok((
+                    bincode::deserialize(&queue.sender).context(Deserialize)?,
+                    queue.depth,
+                ))
+            })
+            .collect()
+    }
+
+    /// Add or remove a node of the network, only allowed for the admin
+    ///
+    /// Every node applies it once agreed on, as with any transaction.
+    pub async fn change_membership(
+        &mut self,
+        user: &sign::KeyPair,
+        sequence: sieve::Sequence,
+        change: MembershipChange,
+    ) -> Result<()> {
+        use change_membership_request::{Change, Node};
+
+        let signature = user
+            .sign(&Operation::Membership {
+                change: change.clone(),
+                sequence,
+            })
+            .expect("sign failed");
+
+        self.0
+            .change_membership(tonic::Request::new(ChangeMembershipRequest {
+                sender: bincode::serialize(&user.public()).context(Serialize)?,
+                sequence,
+                change: Some(match change {
+                    MembershipChange::Add {
+                        public_key,
+                        address,
+                        rpc,
+                        sign_key,
+                    } => Change::Add(Node {
+                        public_key: bincode::serialize(&public_key).context(Serialize)?,
+                        address,
+                        rpc,
+                        sign_key: bincode::serialize(&sign_key).context(Serialize)?,
+                    }),
+                    MembershipChange::Remove { public_key } => {
+                        Change::Remove(bincode::serialize(&public_key).context(Serialize)?)
+                    }
+                }),
+                signature: bincode::serialize(&signature).context(Serialize)?,
+            }))
+            .await
+            .context(Rpc)
+            .map(|_| ())
+    }
+
+    /// Check that the reply to the request with the given nonce is signed by the expected node,
+    /// if any
+    fn verify_reply(
+        &self,
+        reply: &SignedReply,
+        signature: Option<NodeSignature>,
+        nonce: u64,
+    ) -> Result<()> {
+        let node = match &self.1 {
+            Some(node) => node,
+            None => return Ok(()),
+        };
+
+        let signature = bincode::deserialize(&signature.context(MissingSignature)?.signature)
+            .context(Deserialize)?;
+        ensure!(reply.verify(&signature, node, nonce), InvalidSignature);
+
+        Ok(())
+    }
+}
+
+/// Client asking many nodes, only trusting a reply once enough of them agree on it
+#[derive(Clone)]
+pub struct QuorumClient {
+    clients: Vec<Client>,
+    quorum: usize,
+}
+
+impl QuorumClient {
+    /// Ask every given client, requiring `quorum` of them to agree
+    ///
+    /// Each client has to check the replies' signatures, see [`Client::with_node_key`], a node
+    /// given more than once being only asked once. The quorum has to be reachable, between one
+    /// and the number of distinct nodes.
+    pub fn new(clients: Vec<Client>, quorum: usize) -> Result<Self> {
+        let mut nodes = Vec::with_capacity(clients.len());
+        let mut distinct = Vec::with_capacity(clients.len());
+        for client in clients {
+            let node = client.1.context(MissingNodeKey)?;
+            if !nodes.contains(&node) {
+                nodes.push(node);
+                distinct.push(client);
+            }
+        }
+
+        ensure!(
+            (1..=distinct.len()).contains(&quorum),
+            InvalidQuorum {
+                quorum,
+                nodes: distinct.len()
+            }
+        );
+
+        Ok(Self {
+            clients: distinct,
+            quorum,
+        })
+    }
+
+    /// Return the balance of the user, in [`crate::NATIVE_ASSET`]
+    pub async fn get_balance(&self, user: &sign::PublicKey) -> Result<u64> {
+        self.get_asset_balance(user, NATIVE_ASSET.to_owned()).await
+    }
+
+    /// Return the balance of the user in the given asset
+    pub async fn get_asset_balance(&self, user: &sign::PublicKey, asset: String) -> Result<u64> {
+        self.ask(|mut client| {
+            let asset = asset.clone();
+            async move { client.get_asset_balance(user, asset).await }
+        })
+        .await
+    }
+
+    /// Get the latest used sequence
+    pub async fn get_last_sequence(&self, user: &sign::PublicKey) -> Result<sieve::Sequence> {
+        self.ask(|mut client| async move { client.get_last_sequence(user).await })
+            .await
+    }
+
+    /// Get the state of the transaction sent by the user with the given sequence
+    ///
+    /// Only the state is agreed on, as the rest of the transaction, such as its timestamp,
+    /// is local to each node.
+    pub async fn get_transaction_state(
+        &self,
+        user: &sign::PublicKey,
+        sequence: sieve::Sequence,
+    ) -> Result<TransactionState> {
+        self.ask(|mut client| async move {
+            client
+                .get_transaction(user, sequence)
+                .await
+                // every output shares the state
+                .map(|outputs| outputs[0].state.clone())
+        })
+        .await
+    }
+
+    async fn ask<T, F, Fut>(&self, query: F) -> Result<T>
+    where
+        T: PartialEq,
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        agree(
+            self.clients
+                .iter()
+                .cloned()
+                .map(query)
+                .collect::<FuturesUnordered<_>>(),
+            self.quorum,
+        )
+        .await
+    }
+}
+
+/// Return the first reply given by `quorum` of the replies, without waiting for the others
+async fn agree<T: PartialEq>(
+    mut replies: impl Stream<Item = Result<T>> + Unpin,
+    quorum: usize,
+) -> Result<T> {
+    let mut agreeing = Vec::<(T, usize)>::new();
+    let mut replied = 0;
+
+    while let Some(reply) = replies.next().await {
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(_) => continue,
+        };
+        replied += 1;
+
+        let index = match agreeing.iter().position(|(other, _)| *other == reply) {
+            Some(index) => index,
+            None => {
+                agreeing.push((reply, 0));
+                agreeing.len() - 1
+            }
+        };
+        agreeing[index].1 += 1;
+
+        if agreeing[index].1 >= quorum {
+            return Ok(agreeing.swap_remove(index).0);
+        }
+    }
+
+    ensure!(replied >= quorum, NotEnoughReplies { replied, quorum });
+    Disagreement { replied, quorum }.fail()
+}
+
+fn transfer_request(
+    user: &sign::KeyPair,
+    sequence: sieve::Sequence,
+    recipient: sign::PublicKey,
+    asset: String,
+    amount: u64,
+    memo: Option<String>,
+) -> Result<SendAssetRequest> {
+    let message = Operation::Transfer(ThinTransaction {
+        recipient,
+        asset: asset.clone(),
+        amount,
+        memo: memo.clone(),
+    });
+    let signature = user.sign(&message).expect("sign failed");
+
+    Ok(SendAssetRequest {
+        sender: bincode::serialize(&user.public()).context(Serialize)?,
+        sequence,
+        recipient: bincode::serialize(&recipient).context(Serialize)?,
+        amount,
+        signature: bincode::serialize(&signature).context(Serialize)?,
+        memo: memo.unwrap_or_default(),
+        asset,
+    })
+}
+
+fn verify_account_proof(user: &sign::PublicKey, proof: AccountProof) -> Result<ProvenAccount> {
+    use account_proof::sibling::Side;
+
+    let to_digest = |bytes: Vec<u8>| bytes.try_into().ok().context(InvalidProof);
+
+    let balances = proof.balances.into_iter().collect();
+    let state_root = proof.state_root.try_into().ok().context(InvalidStateRoot)?;
+    let node = bincode::deserialize(&proof.node).context(Deserialize)?;
+    let signature = bincode::deserialize(&proof.signature).context(Deserialize)?;
+
+    let path = state::Proof {
+        siblings: proof
+            .siblings
+            .into_iter()
+            .map(|sibling| match sibling.side.context(InvalidProof)? {
+                Side::Left(left) => to_digest(left).map(state::Sibling::Left),
+                Side::Right(right) => to_digest(right).map(state::Sibling::Right),
+            })
+            .collect::<Result<_>>()?,
+    };
+
+    let account = state::account_hash(user, proof.last_sequence, &balances).context(Serialize)?;
+    ensure!(path.root(account) == state_root, InvalidProof);
+    ensure!(
+        state::verify_state_root(&state_root, &signature, &node),
+        InvalidSignature
+    );
+
+    Ok(ProvenAccount {
+        last_sequence: proof.last_sequence,
+        balances,
+        state_root,
+        node,
+    })
+}
+
+fn full_transaction_from_proto(tx: &proto::FullTransaction) -> Result<FullTransaction> {
+    use full_transaction::{FailureReason as Reason, State};
+
+    Ok(FullTransaction {
+        timestamp: chrono::DateTime::parse_from_rfc3339(&tx.timestamp)
+            .context(DeserializeTimestamp)?
+            .into(),
+        sender: bincode::deserialize(&tx.sender).context(Deserialize)?,
+        sender_sequence: tx.sender_sequence,
+        recipient: bincode::deserialize(&tx.recipient).context(Deserialize)?,
+        asset: if tx.asset.is_empty() {
+            NATIVE_ASSET.to_owned()
+        } else {
+            tx.asset.clone()
+        },
+        amount: tx.amount,
+        memo: Some(tx.memo.clone()).filter(|memo| !memo.is_empty()),
+        state: match State::from_i32(tx.state).context(DeserializeState)? {
+            State::Pending => TransactionState::Pending,
+            State::Success => TransactionState::Success,
+            State::Failure => TransactionState::Failure(
+                match Reason::from_i32(tx.failure_reason).context(DeserializeState)? {
+                    Reason::InsufficientFunds => FailureReason::InsufficientFunds,
+                    Reason::Overflow => FailureReason::Overflow,
+                    Reason::SequenceGap => FailureReason::SequenceGap,
+                    Reason::Expired => FailureReason::Expired,
+                    Reason::NoSuchAccount => FailureReason::NoSuchAccount,
+                    Reason::Other => FailureReason::Other,
+                },
+            ),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, stream};
+
+    use super::*;
+
+    fn unavailable() -> Result<u64> {
+        Err(Error::Rpc {
+            source: tonic::Status::unavailable("down"),
+        })
+    }
+
+    #[test]
+    fn agreed_reply_needs_quorum() {
+        let reply = block_on(agree(stream::iter([Ok(1), Ok(2), unavailable(), Ok(2)]), 2))
+            .expect("to agree on a reply");
+        assert_eq!(2, reply);
+
+        let reply = block_on(agree(stream::iter([Ok(1)]), 1)).expect("to agree on a reply");
+        assert_eq!(1, reply);
+    }
+
+    #[test]
+    fn disagreement_is_told_apart() {
+        assert!(matches!(
+            block_on(agree(stream::iter([Ok(1), Ok(2), unavailable()]), 2)),
+            Err(Error::Disagreement {
+                replied: 2,
+                quorum: 2
+            })
+        ));
+        assert!(matches!(
+            block_on(agree(
+                stream::iter([Ok(1), unavailable(), unavailable()]),
+                2
+            )),
+            Err(Error::NotEnoughReplies {
+                replied: 1,
+                quorum: 2
+            })
+        ));
+    }
 }
@@ -2,38 +2,153 @@
 
 //! Client and related struct to connect to an AT2 node
 
-use drop::crypto::sign;
+use std::{collections::BTreeMap, fmt};
+
+use drop::crypto::{key::exchange, sign};
 use serde::{Deserialize, Serialize};
 
 pub mod client;
+pub mod state;
+pub mod wallet;
 
 /// `tonic-build` generated files
 #[allow(missing_docs)]
 pub mod proto;
 
+/// Sequence used by a user to create its account, before any transfer
+pub const CREATE_ACCOUNT_SEQUENCE: sieve::Sequence = 0;
+
+/// Asset existing since genesis, the only one not issued via [`Operation::Issue`]
+pub const NATIVE_ASSET: &str = "native";
+
+/// Maximum size in bytes of an asset name
+pub const ASSET_MAX_SIZE: usize = 32;
+
+/// Maximum size in bytes of a [`ThinTransaction::memo`]
+pub const MEMO_MAX_SIZE: usize = 256;
+
+/// Maximum number of outputs of a [`Operation::Batch`]
+pub const BATCH_MAX_SIZE: usize = 1024;
+
 /// Type of message sent via sieve
 #[drop::message]
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    /// Create the sender's account, sent with [`CREATE_ACCOUNT_SEQUENCE`]
+    CreateAccount,
+    /// Send asset from the sender's account
+    Transfer(ThinTransaction),
+    /// Send asset from the sender's account to many, every transfer applied or none
+    Batch(Vec<ThinTransaction>),
+    /// Create some amount of a new asset, only allowed for the issuer
+    Issue {
+        /// Name of the asset, can't be [`NATIVE_ASSET`]
+        asset: String,
+        /// How many asset to create in the issuer's account
+        amount: u64,
+        /// Sequence it is sent with, signed so that it can't be replayed with another one
+        sequence: sieve::Sequence,
+    },
+    /// Add or remove a node of the network, only allowed for the admin
+    Membership {
+        /// Nodes to add or remove
+        change: MembershipChange,
+        /// Sequence it is sent with, signed so that it can't be replayed with another one
+        sequence: sieve::Sequence,
+    },
+}
+
+/// Change to the nodes of the network, applied by every node once agreed on
+#[drop::message]
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub enum MembershipChange {
+    /// Start connecting to a new node
+    Add {
+        /// Key used by the node in the network
+        public_key: exchange::PublicKey,
+        /// Where to reach the node
+        address: String,
+        /// Where the node serves its RPC, for the others to catch up with it
+        rpc: String,
+        /// Key signing the node's replies
+        sign_key: sign::PublicKey,
+    },
+    /// Stop connecting to a node
+    Remove {
+        /// Key used by the node in the network
+        public_key: exchange::PublicKey,
+    },
+}
+
+/// If the asset name can be issued
+pub fn is_issuable_asset(asset: &str) -> bool {
+    !asset.is_empty() && asset.len() <= ASSET_MAX_SIZE && asset != NATIVE_ASSET
+}
+
+/// Asset transfer, from the sender of the [`Operation`]
+#[drop::message]
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct ThinTransaction {
     /// User receiving the amount
     pub recipient: sign::PublicKey,
+    /// Which asset to send
+    pub asset: String,
     /// How many asset to send
     pub amount: u64,
+    /// Free text attached by the sender, up to [`MEMO_MAX_SIZE`]
+    pub memo: Option<String>,
+}
+
+impl ThinTransaction {
+    /// If the memo is short enough
+    pub fn has_valid_memo(&self) -> bool {
+        self.memo.iter().all(|memo| memo.len() <= MEMO_MAX_SIZE)
+    }
 }
 
 /// What is the status of the transaction
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum TransactionState {
     /// Waiting for network confirmation
     Pending,
     /// Processed, committed
     Success,
     /// Unable to process it
-    Failure,
+    Failure(FailureReason),
+}
+
+/// Why a transaction was not processed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FailureReason {
+    /// Sender doesn't own enough asset
+    InsufficientFunds,
+    /// Recipient would own more asset than representable
+    Overflow,
+    /// Sequence isn't the one following the last used by the sender
+    SequenceGap,
+    /// Not processable before its time to live expired
+    Expired,
+    /// Recipient has no account
+    NoSuchAccount,
+    /// Any other error, only known to the node
+    Other,
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InsufficientFunds => "insufficient funds",
+            Self::Overflow => "overflow",
+            Self::SequenceGap => "sequence gap",
+            Self::Expired => "expired",
+            Self::NoSuchAccount => "no such account",
+            Self::Other => "other",
+        })
+    }
 }
 
 /// Transaction when committed to memory
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct FullTransaction {
     /// When the transaction was stored
     pub timestamp: chrono::DateTime<chrono::Utc>,
@@ -43,8 +158,112 @@ pub struct FullTransaction {
     pub sender_sequence: sieve::Sequence,
     /// User receiving it
     pub recipient: sign::PublicKey,
+    /// Which asset to send
+    pub asset: String,
     /// How many asset to send
     pub amount: u64,
     /// Processing status
     pub state: TransactionState,
+    /// Free text attached by the sender
+    pub memo: Option<String>,
+}
+
+/// Which transactions of an account to consider
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HistoryDirection {
+    /// Sent by the account
+    Sent,
+    /// Received by the account
+    Received,
+    /// Either sent or received by the account
+    Both,
+}
+
+/// What a node replied to a query, signed with its key to prove what it told
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignedReply {
+    /// Owned amount of an asset
+    Balance {
+        /// Owner of the account
+        user: sign::PublicKey,
+        /// Name of the asset, never empty
+        asset: String,
+        /// How many asset the user owns
+        amount: u64,
+    },
+    /// Owned amount of every asset
+    Portfolio {
+        /// Owner of the account
+        user: sign::PublicKey,
+        /// How many of each asset the user owns
+        balances: BTreeMap<String, u64>,
+    },
+    /// Latest used sequence
+    LastSequence {
+        /// Owner of the account
+        user: sign::PublicKey,
+        /// Latest sequence used by the user
+        sequence: sieve::Sequence,
+    },
+    /// Every output of a transaction, each as encoded in [`proto::FullTransaction`]
+    Transaction(Vec<Vec<u8>>),
+    /// Accounts and pending payloads, given to the nodes catching up
+    Ledger {
+        /// Number of operations applied to the accounts
+        height: u64,
+        /// Root of the accounts, see [`state`]
+        state_root: state::Digest,
+        /// Delivered payloads not yet applied, as encoded in [`proto::GetLedgerReply`]
+        pending: Vec<u8>,
+    },
+}
+
+impl SignedReply {
+    /// Sign the reply with the node's key, along with its public key and the request's nonce
+    ///
+    /// The nonce is chosen by the client, so that an older reply can't be given again.
+    pub fn sign(
+        &self,
+        node: &sign::KeyPair,
+        nonce: u64,
+    ) -> Result<sign::Signature, sign::SignError> {
+        node.sign(&(node.public(), nonce, self))
+    }
+
+    /// If the reply to the request with the given nonce was signed by the given node
+    pub fn verify(&self, signature: &sign::Signature, node: &sign::PublicKey, nonce: u64) -> bool {
+        signature.verify(&(node, nonce, self), node).is_ok()
+    }
+}
+
+/// Unpredictable nonce, to ask for a [`SignedReply`]
+pub fn nonce() -> u64 {
+    let key = bincode::serialize(&sign::KeyPair::random().public()).expect("to encode key");
+    let hash = blake3::hash(&key);
+
+    u64::from_le_bytes(
+        hash.as_bytes()[..8]
+            .try_into()
+            .expect("hash longer than a nonce"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_reply_is_bound_to_node_and_nonce() {
+        let node = sign::KeyPair::random();
+        let reply = SignedReply::LastSequence {
+            user: sign::KeyPair::random().public(),
+            sequence: 1,
+        };
+        let nonce = nonce();
+
+        let signature = reply.sign(&node, nonce).expect("to sign reply");
+        assert!(reply.verify(&signature, &node.public(), nonce));
+        assert!(!reply.verify(&signature, &node.public(), nonce.wrapping_add(1)));
+        assert!(!reply.verify(&signature, &sign::KeyPair::random().public(), nonce));
+    }
 }
@@ -0,0 +1,236 @@
+//! Hashing of the accounts, to check that nodes agree on them
+
+use std::collections::BTreeMap;
+
+use drop::crypto::sign;
+use serde::{Deserialize, Serialize};
+
+/// Hash of part of the state
+pub type Digest = [u8; 32];
+
+/// Prefixes of the hashed content, so that an account can't be taken for a node of the tree
+const ACCOUNT_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// Context of a signed state root, so that the signature can't be taken for another message
+const SIGNED_STATE_ROOT_CONTEXT: &str = "at2 state root";
+
+/// Sibling of a node of the tree, on the path from an account to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sibling {
+    /// Hashed before the node
+    Left(Digest),
+    /// Hashed after the node
+    Right(Digest),
+}
+
+/// Path from an account to the state root
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    /// Siblings met on the way up, starting from the account's
+    pub siblings: Vec<Sibling>,
+}
+
+impl Proof {
+    /// State root obtained by following the path from the given account's hash
+    pub fn root(&self, account: Digest) -> Digest {
+        self.siblings
+            .iter()
+            .fold(account, |node, sibling| match sibling {
+                Sibling::Left(left) => node_hash(left, &node),
+                Sibling::Right(right) => node_hash(&node, right),
+            })
+    }
+}
+
+/// Merkle tree over the accounts' hashes, kept to prove them without hashing every level again
+#[derive(Debug, Clone)]
+pub struct Tree {
+    /// Every level, from the accounts' hashes up to the root
+    levels: Vec<Vec<Digest>>,
+}
+
+impl Tree {
+    /// Build the tree over the accounts' hashes, sorted by user
+    pub fn new(accounts: Vec<Digest>) -> Self {
+        let mut levels = vec![accounts];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parent = parent_level(level);
+            levels.push(parent);
+        }
+
+        Self { levels }
+    }
+
+    /// Root of the tree, the hash of nothing without any account
+    ///
+    /// A node without sibling is moved up as is.
+    pub fn root(&self) -> Digest {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => *root,
+            None => blake3::hash(&[]).into(),
+        }
+    }
+
+    /// Proof that the account at `index` is part of the root
+    pub fn prove(&self, mut index: usize) -> Option<Proof> {
+        if index >= self.levels[0].len() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        for level in self.levels.iter().take(self.levels.len() - 1) {
+            if index % 2 == 1 {
+                siblings.push(Sibling::Left(level[index - 1]));
+            } else if let Some(right) = level.get(index + 1) {
+                siblings.push(Sibling::Right(*right));
+            }
+
+            index /= 2;
+        }
+
+        Some(Proof { siblings })
+    }
+}
+
+/// Hash of an account, a leaf of the state tree
+pub fn account_hash(
+    user: &sign::PublicKey,
+    last_sequence: sieve::Sequence,
+    balances: &BTreeMap<String, u64>,
+) -> Result<Digest, bincode::Error> {
+    let encoded = bincode::serialize(&(user, last_sequence, balances))?;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[ACCOUNT_PREFIX]);
+    hasher.update(&encoded);
+
+    Ok(hasher.finalize().into())
+}
+
+/// Root of the Merkle tree over the accounts' hashes, sorted by user
+///
+/// A node without sibling is moved up as is, and the root of no account is the hash of nothing.
+pub fn state_root(accounts: &[Digest]) -> Digest {
+    Tree::new(accounts.to_vec()).root()
+}
+
+/// Proof that the account at `index` is part of the [`state_root`] of the accounts
+pub fn prove(accounts: &[Digest], index: usize) -> Option<Proof> {
+    Tree::new(accounts.to_vec()).prove(index)
+}
+
+/// Sign the state root, for clients to check who vouched for it
+pub fn sign_state_root(
+    keypair: &sign::KeyPair,
+    state_root: &Digest,
+) -> Result<sign::Signature, sign::SignError> {
+    keypair.sign(&(SIGNED_STATE_ROOT_CONTEXT, state_root))
+}
+
+/// If the state root was signed by the given node
+pub fn verify_state_root(
+    state_root: &Digest,
+    signature: &sign::Signature,
+    node: &sign::PublicKey,
+) -> bool {
+    signature
+        .verify(&(SIGNED_STATE_ROOT_CONTEXT, state_root), node)
+        .is_ok()
+}
+
+fn parent_level(level: &[Digest]) -> Vec<Digest> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!("chunks of two"),
+        })
+        .collect()
+}
+
+fn node_hash(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts(count: u64) -> Vec<Digest> {
+        (0..count)
+            .map(|balance| {
+                account_hash(
+                    &sign::KeyPair::random().public(),
+                    0,
+                    &[("native".to_owned(), balance)].into_iter().collect(),
+                )
+                .expect("to hash account")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn state_root_depends_on_every_account() {
+        let accounts = accounts(5);
+        let root = state_root(&accounts);
+
+        for changed in 0..accounts.len() {
+            let mut other = accounts.clone();
+            other[changed][0] ^= 1;
+
+            assert_ne!(root, state_root(&other));
+        }
+        assert_ne!(root, state_root(&accounts[..4]));
+    }
+
+    #[test]
+    fn proof_leads_to_state_root() {
+        for count in 1..8 {
+            let accounts = accounts(count);
+            let root = state_root(&accounts);
+
+            for (index, account) in accounts.iter().enumerate() {
+                let proof = prove(&accounts, index).expect("to prove account");
+                assert_eq!(root, proof.root(*account));
+
+                let mut other = *account;
+                other[0] ^= 1;
+                assert_ne!(root, proof.root(other));
+            }
+            assert!(prove(&accounts, accounts.len()).is_none());
+        }
+    }
+
+    #[test]
+    fn signed_state_root_is_verified() {
+        let node = sign::KeyPair::random();
+        let root = state_root(&accounts(3));
+
+        let signature = sign_state_root(&node, &root).expect("to sign state root");
+        assert!(verify_state_root(&root, &signature, &node.public()));
+        assert!(!verify_state_root(
+            &state_root(&[]),
+            &signature,
+            &node.public()
+        ));
+        assert!(!verify_state_root(
+            &root,
+            &signature,
+            &sign::KeyPair::random().public()
+        ));
+    }
+
+    #[test]
+    fn state_root_of_single_account_is_its_hash() {
+        let accounts = accounts(1);
+
+        assert_eq!(accounts[0], state_root(&accounts));
+    }
+}
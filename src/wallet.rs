@@ -0,0 +1,145 @@
+//! Higher-level client, managing the sequences of a user
+
+use drop::crypto::sign;
+use futures::StreamExt;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::sync::Mutex;
+
+use crate::{
+    client::{self, Client},
+    FullTransaction, TransactionState,
+};
+
+/// Error generated by the wallet
+#[derive(Debug, Snafu)]
+pub enum Error {
+    /// Requesting the node via the underlying client
+    #[snafu(display("request: {}", source))]
+    Request {
+        /// Source of the error
+        source: client::Error,
+    },
+    /// Server stopped following the transaction before it resolved
+    Unresolved,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Send assets for a given user without caring about the sequences
+///
+/// The next sequence is fetched from the node on first use then allocated locally, so that
+/// concurrent sends are broadcasted in order. It is checked again against the node's after a
+/// transaction failed or the node didn't answer as expected, without going back as the
+/// sequences allocated meanwhile can still be in flight.
+pub struct Wallet {
+    client: Client,
+    user: sign::KeyPair,
+    sequences: Mutex<Sequences>,
+}
+
+/// Sequences allocated by a [`Wallet`]
+#[derive(Default)]
+struct Sequences {
+    /// Sequence to use for the next transaction, unknown before the first one
+    next: Option<sieve::Sequence>,
+    /// If the node can be ahead, such as when the user sent from elsewhere
+    stale: bool,
+}
+
+impl Wallet {
+    /// Create a wallet sending for `user` via the given client
+    pub fn new(client: Client, user: sign::KeyPair) -> Self {
+        Self {
+            client,
+            user,
+            sequences: Mutex::default(),
+        }
+    }
+
+    /// Send a given number of asset to the given user, waiting for it to be processed
+    ///
+    /// It returns the resolved transaction, its state telling if it failed and why.
+    pub async fn send_asset(
+        &self,
+        recipient: sign::PublicKey,
+        asset: String,
+        amount: u64,
+        memo: Option<String>,
+    ) -> Result<FullTransaction> {
+        let mut client = self.client.clone();
+
+        let sequence = {
+            let mut sequences = self.sequences.lock().await;
+
+            let sequence = match sequences.next {
+                Some(sequence) if !sequences.stale => sequence,
+                local => {
+                    let fetched = client
+                        .get_last_sequence(&self.user.public())
+                        .await
+                        .context(Request)?
+                        + 1;
+                    sequences.stale = false;
+
+                    local.map_or(fetched, |local| local.max(fetched))
+                }
+            };
+
+            // broadcast while locked to keep the sends ordered
+            if let Err(err) = client
+                .send_asset(&self.user, sequence, recipient, asset, amount, memo)
+                .await
+            {
+                // such as rejected for using a stale sequence, so not in flight
+                sequences.next = Some(sequence);
+                sequences.stale = true;
+                return Err(err).context(Request);
+            }
+            sequences.next = Some(sequence + 1);
+
+            sequence
+        };
+
+        let resolved = self.wait_for(&mut client, sequence).await;
+        if !matches!(
+            resolved,
+            Ok(FullTransaction {
+                state: TransactionState::Success,
+                ..
+            })
+        ) {
+            self.resync().await;
+        }
+
+        resolved
+    }
+
+    /// Follow the transaction sent with `sequence` until it resolves
+    async fn wait_for(
+        &self,
+        client: &mut Client,
+        sequence: sieve::Sequence,
+    ) -> Result<FullTransaction> {
+        let mut states = Box::pin(
+            client
+                .watch_transaction(&self.user.public(), sequence)
+                .await
+                .context(Request)?,
+        );
+
+        let mut last = None;
+        while let Some(tx) = states.next().await {
+            last = Some(tx.context(Request)?);
+        }
+        let tx = last.context(Unresolved)?;
+
+        ensure!(tx.state != TransactionState::Pending, Unresolved);
+
+        Ok(tx)
+    }
+
+    /// Check the next sequence against the node's on next send
+    pub async fn resync(&self) {
+        self.sequences.lock().await.stale = true;
+    }
+}
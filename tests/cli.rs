@@ -71,18 +71,26 @@ impl Drop for Server {
 type ServerConfig = Vec<u8>;
 type NodeConfig = Vec<u8>;
 
-fn gen_config(node: &SocketAddr, rpc: &SocketAddr) -> (ServerConfig, NodeConfig) {
-    let full_config = cmd!(
-        SERVER_BIN,
-        "config",
-        "new",
-        &node.to_string(),
-        &rpc.to_string()
-    )
-    .stdout_capture()
-    .run()
-    .expect("generate config")
-    .stdout;
+fn gen_config(
+    node: &SocketAddr,
+    rpc: &SocketAddr,
+    faucet: Option<&str>,
+) -> (ServerConfig, NodeConfig) {
+    let mut args = vec![
+        "config".to_owned(),
+        "new".to_owned(),
+        node.to_string(),
+        rpc.to_string(),
+    ];
+    if let Some(faucet) = faucet {
+        args.extend(["--faucet".to_owned(), faucet.to_owned()]);
+    }
+
+    let full_config = duct::cmd(SERVER_BIN, args)
+        .stdout_capture()
+        .run()
+        .expect("generate config")
+        .stdout;
 
     let node_config = cmd!(SERVER_BIN, "config", "get-node")
         .stdin_bytes(full_config.clone())
@@ -134,7 +142,7 @@ async fn wait_until_connect(server: &Server, to_probe: &SocketAddr) {
 async fn server_started_twice_fails() {
     let (node, rpc) = (next_test_ip4(), next_test_ip4());
 
-    let (server_config, _) = gen_config(&node, &rpc);
+    let (server_config, _) = gen_config(&node, &rpc, None);
 
     let first_server = start_server(server_config.clone());
     join_all(vec![
@@ -159,14 +167,34 @@ async fn server_started_twice_fails() {
     assert_eq!(exit, Some(io::ErrorKind::Other));
 }
 
-async fn start_network(size: usize) -> (Vec<Server>, Url) {
+fn get_public_key(config: String) -> String {
+    cmd!(CLIENT_BIN, "config", "get-public-key")
+        .stdin_bytes(config)
+        .read()
+        .expect("get public key")
+}
+
+async fn start_network(size: usize) -> (Vec<Server>, Url, String) {
     let addresses = repeat_with(|| (next_test_ip4(), next_test_ip4()))
         .take(size)
         .collect::<Vec<_>>();
 
+    let rpc = addresses
+        .iter()
+        .map(|(_, rpc)| rpc)
+        .copied()
+        .next()
+        .map(|addr| Url::parse(&format!("http://{}", addr)).expect("format as URL"))
+        .expect("zero sized network");
+
+    let faucet = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
+        .read()
+        .expect("create faucet");
+    let faucet_pubkey = get_public_key(faucet.clone());
+
     let (mut server_configs, node_configs): (Vec<_>, Vec<_>) = addresses
         .iter()
-        .map(|(node, rpc)| gen_config(node, rpc))
+        .map(|(node, rpc)| gen_config(node, rpc, Some(&faucet_pubkey)))
         .unzip();
 
     server_configs
@@ -196,15 +224,7 @@ async fn start_network(size: usize) -> (Vec<Server>, Url) {
     }))
     .await;
 
-    let rpc = addresses
-        .iter()
-        .map(|(_, rpc)| rpc)
-        .copied()
-        .next()
-        .map(|addr| Url::parse(&format!("http://{}", addr)).expect("format as URL"))
-        .expect("zero sized network");
-
-    (servers, rpc)
+    (servers, rpc, faucet)
 }
 
 #[tokio::test]
@@ -214,7 +234,7 @@ async fn can_run_network() {
 
 #[tokio::test]
 async fn client_without_servers_fails() {
-    let (_, rpc) = start_network(2).await;
+    let (_, rpc, _) = start_network(2).await;
 
     let recipient = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
         .pipe(cmd!(CLIENT_BIN, "config", "get-public-key"))
@@ -222,7 +242,14 @@ async fn client_without_servers_fails() {
         .expect("recipient public key");
 
     cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
-        .pipe(cmd!(CLIENT_BIN, "send-asset", "1", &recipient, "10"))
+        .pipe(cmd!(
+            CLIENT_BIN,
+            "send-asset",
+            "--sequence",
+            "1",
+            &recipient,
+            "10"
+        ))
         .run()
         .expect_err("send asset");
 }
@@ -236,15 +263,65 @@ fn get_balance(config: String) -> usize {
         .expect("parse asset amount as usize")
 }
 
+async fn create_client(rpc: &Url) -> String {
+    let config = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
+        .read()
+        .expect("create client");
+
+    cmd!(CLIENT_BIN, "create-account")
+        .stdin_bytes(config.clone())
+        .run()
+        .expect("create account");
+
+    let timeout = Instant::now() + TIMEOUT;
+    while Instant::now() < timeout {
+        if cmd!(CLIENT_BIN, "get-last-sequence")
+            .stdin_bytes(config.clone())
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .expect("get last sequence")
+            .status
+            .success()
+        {
+            return config;
+        }
+
+        tokio::time::sleep(TICK).await;
+    }
+
+    panic!("timeout expired");
+}
+
 #[tokio::test]
-async fn new_client_has_some_asset() {
-    let (_servers, rpc) = start_network(3).await;
+async fn new_client_has_no_account() {
+    let (_servers, rpc, _) = start_network(3).await;
 
     let config = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
         .read()
-        .expect("create sender");
+        .expect("create client");
+
+    cmd!(CLIENT_BIN, "get-balance")
+        .stdin_bytes(config)
+        .run()
+        .expect_err("get balance of unknown account");
+}
+
+#[tokio::test]
+async fn created_client_has_no_asset() {
+    let (_servers, rpc, _) = start_network(3).await;
+
+    let config = create_client(&rpc).await;
+
+    assert_eq!(get_balance(config), 0);
+}
+
+#[tokio::test]
+async fn faucet_has_some_asset() {
+    let (_servers, _, faucet) = start_network(3).await;
 
-    assert!(get_balance(config) > 0);
+    assert!(get_balance(faucet) > 0);
 }
 
 fn transfer(
@@ -253,14 +330,12 @@ fn transfer(
     receiver_config: String,
     amount: usize,
 ) {
-    let second_client = cmd!(CLIENT_BIN, "config", "get-public-key")
-        .stdin_bytes(receiver_config)
-        .read()
-        .expect("get public key");
+    let second_client = get_public_key(receiver_config);
 
     cmd!(
         CLIENT_BIN,
         "send-asset",
+        "--sequence",
         &sender_sequence.to_string(),
         &second_client,
         &amount.to_string(),
@@ -297,38 +372,88 @@ async fn wait_for_sequence(config: String, sequence: sieve::Sequence) {
 async fn transfer_increment_sequence() {
     const AMOUNT: usize = 10;
 
-    let (_servers, rpc) = start_network(3).await;
+    let (_servers, rpc, faucet) = start_network(3).await;
 
-    let sender = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
-        .read()
-        .expect("create sender");
-
-    let receiver = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
-        .read()
-        .expect("create receiver");
+    let receiver = create_client(&rpc).await;
 
-    transfer(sender.clone(), 1, receiver.clone(), AMOUNT);
+    transfer(faucet.clone(), 1, receiver, AMOUNT);
 
-    wait_for_sequence(sender.clone(), 1).await;
+    wait_for_sequence(faucet, 1).await;
 }
 
 #[tokio::test]
 async fn can_send_asset() {
     const AMOUNT: usize = 10;
 
-    let (_servers, rpc) = start_network(3).await;
+    let (_servers, rpc, faucet) = start_network(3).await;
 
-    let sender = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
-        .read()
-        .expect("create sender");
+    let receiver = create_client(&rpc).await;
 
-    let receiver = cmd!(CLIENT_BIN, "config", "new", &rpc.to_string())
-        .read()
-        .expect("create receiver");
+    let faucet_balance = get_balance(faucet.clone());
+
+    transfer(faucet.clone(), 1, receiver.clone(), AMOUNT);
+
+    wait_for_sequence(faucet.clone(), 1).await;
+
+    assert_eq!(get_balance(faucet), faucet_balance - AMOUNT);
+    assert_eq!(get_balance(receiver), AMOUNT);
+}
+
+#[tokio::test]
+async fn wallet_resyncs_after_stale_sequence() {
+    use at2_node::{
+        client::Client, wallet::Wallet, FullTransaction, TransactionState, NATIVE_ASSET,
+    };
+    use drop::crypto::sign;
 
-    transfer(sender.clone(), 1, receiver.clone(), AMOUNT);
+    let (_servers, rpc, _) = start_network(3).await;
 
-    wait_for_sequence(sender.clone(), 1).await;
+    let mut client = Client::new(rpc.as_str().parse().expect("parse as URI"));
+    let user = sign::KeyPair::random();
+
+    client.create_account(&user).await.expect("create account");
+    let timeout = Instant::now() + TIMEOUT;
+    while client.get_last_sequence(&user.public()).await.is_err() {
+        assert!(Instant::now() < timeout, "timeout expired");
+        tokio::time::sleep(TICK).await;
+    }
+
+    let wallet = Wallet::new(client.clone(), sign::KeyPair::from(user.private()));
+    let send = || wallet.send_asset(user.public(), NATIVE_ASSET.to_owned(), 0, None);
+
+    let first = send().await.expect("send with wallet");
+    assert_eq!(
+        (1, TransactionState::Success),
+        (first.sender_sequence, first.state)
+    );
+
+    // use the sequence cached by the wallet behind its back
+    client
+        .send_asset(&user, 2, user.public(), NATIVE_ASSET.to_owned(), 0, None)
+        .await
+        .expect("send without wallet");
+    let timeout = Instant::now() + TIMEOUT;
+    while client
+        .get_last_sequence(&user.public())
+        .await
+        .expect("get last sequence")
+        != 2
+    {
+        assert!(Instant::now() < timeout, "timeout expired");
+        tokio::time::sleep(TICK).await;
+    }
 
-    assert_eq!(get_balance(sender) + AMOUNT, get_balance(receiver) - AMOUNT);
+    assert!(!matches!(
+        send().await,
+        Ok(FullTransaction {
+            state: TransactionState::Success,
+            ..
+        })
+    ));
+
+    let resynced = send().await.expect("send with resynced wallet");
+    assert_eq!(
+        (3, TransactionState::Success),
+        (resynced.sender_sequence, resynced.state)
+    );
 }
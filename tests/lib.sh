@@ -49,18 +49,24 @@ wait_for_port_connect() {
 	done
 }
 
-declare port_base
+declare port_base faucet
 nodes=''
+node_pids=()
 start_network() {
 	[ -n "$nodes" ] && fail 'nodes already started'
 	port_base=${1:-$((RANDOM + 1024))}
 	local port_top=$((port_base + 2*node_count - 1))
 
+	faucet=$(client config new http://$host_name:$((port_base+1)))
+	local faucet_pubkey=$(echo "$faucet" | client config get-public-key)
+
 	local port configs=()
 
 	for port in $(seq $port_base 2 $port_top)
 	do
-		configs[$((${#configs[@]}+1))]=$(server config new $host_name:{$port,$((port+1))})
+		configs[$((${#configs[@]}+1))]=$(server config new \
+			--faucet "$faucet_pubkey" --issuer "$faucet_pubkey" --admin "$faucet_pubkey" \
+			$host_name:{$port,$((port+1))})
 	done
 
 	local i
@@ -73,8 +79,8 @@ start_network() {
 			node_config+=$'\n'$(echo "${configs[j]}" | server config get-node)
 		done
 
-		echo "$node_config" | server run &
-		nodes+=" $!"
+		echo "$node_config" > node-$i
+		start_node $i
 	done
 
 	for port in $(seq $port_base $port_top)
@@ -83,10 +89,42 @@ start_network() {
 	done
 }
 
+start_node() {
+	local i=$1
+
+	server run < node-$i &
+	node_pids[i]=$!
+	nodes+=" $!"
+}
+
+stop_node() {
+	local i=$1
+	local pid=${node_pids[i]}
+
+	kill -9 $pid
+	wait $pid 2>/dev/null || true
+	nodes=$(echo $nodes | tr ' ' '\n' | grep -vx $pid | xargs)
+	unset node_pids[i]
+}
+
 get_node_rpc() {
-	[ -z "$nodes" ] && fail 'asking for client to stopped nodes'
+	local i=${1:-1}
+
+	[ -z "${node_pids[i]:-}" ] && fail 'asking for client to stopped nodes'
+
+	echo http://$host_name:$((port_base + 2*(i-1) + 1))
+}
+
+create_client() {
+	local config=$(client config new $(get_node_rpc))
+
+	echo "$config" | client create-account
+	until echo "$config" | client get-last-sequence >/dev/null 2>&1
+	do
+		sleep $tick
+	done
 
-	echo http://$host_name:$((port_base+1))
+	echo "$config"
 }
 
 wait_for_sequence() {